@@ -0,0 +1,350 @@
+use crate::config::EngineConfig;
+use crate::notation::{localize, to_san};
+use crate::online::commands::{StockfishInput, StockfishOutput};
+use crate::pgn::GameRecord;
+use crate::stockfish::{EngineHandle, EngineSession, EngineStrength, Score, SearchLimit};
+
+use chess::{Board, BoardStatus, ChessMove, Color};
+use log::debug;
+use std::io;
+
+// thresholds on the loss of winning chances (from -1 to 1), the ones used by Lichess
+const INACCURACY: f64 = 0.1;
+const MISTAKE: f64 = 0.2;
+const BLUNDER: f64 = 0.3;
+// Stockfish's maximum skill level, to analyse at full strength
+const FULL_STRENGTH: i64 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+/// The engine's opinion of a single move.
+#[derive(Debug, Clone)]
+pub(crate) struct MoveAnalysis {
+    pub(crate) chess_move: ChessMove,
+    pub(crate) san: String,
+    pub(crate) side: Color,
+    /// evaluation after the move from white's point of view, `None` if it ends the game by mate
+    pub(crate) eval: Option<Score>,
+    /// the engine's choice in the position before the move
+    pub(crate) best_move: Option<ChessMove>,
+    pub(crate) best_san: Option<String>,
+    /// the line the engine expected after its best move, starting with it
+    pub(crate) best_line: Vec<ChessMove>,
+    pub(crate) centipawn_loss: i32,
+    pub(crate) judgement: Option<Judgement>,
+}
+
+pub(crate) struct GameAnalysis {
+    pub(crate) moves: Vec<MoveAnalysis>,
+}
+
+/// Evaluation of one position: the score from white's point of view, the engine's best move
+/// and the line it expects. Positions where the game is over are not sent to the engine.
+struct PositionEval {
+    score: Option<Score>,
+    best_move: Option<ChessMove>,
+    best_line: Vec<ChessMove>,
+}
+
+/// Replay the game through the engine at the given depth, and judge every move.
+pub(crate) async fn analyse_game(
+    engine: &EngineConfig,
+    record: &GameRecord,
+    depth: u8,
+) -> io::Result<GameAnalysis> {
+    let mut engine_handle = EngineSession::start(engine).await?.spawn();
+    let configure = StockfishInput::Configure {
+        strength: EngineStrength::SkillLevel(FULL_STRENGTH),
+        limit: SearchLimit::Depth(depth),
+    };
+    send(&engine_handle, configure).await?;
+
+    // evaluate the starting position and the one after each move
+    let moves = record.moves();
+    let mut boards = vec![record.start()];
+    for chess_move in &moves {
+        let board = boards.last().unwrap().make_move_new(*chess_move);
+        boards.push(board);
+    }
+
+    let mut evals = Vec::new();
+    for (ply, board) in boards.iter().enumerate() {
+        println!("Analysing position {}/{}...", ply + 1, boards.len());
+        let eval = evaluate(&mut engine_handle, record, &moves[..ply], board).await?;
+        evals.push(eval);
+    }
+
+    // judge each move by the winning chances it lost
+    let mut analysed = Vec::new();
+    for (ply, chess_move) in moves.iter().enumerate() {
+        let side = boards[ply].side_to_move();
+        let before = &evals[ply];
+        let after = &evals[ply + 1];
+
+        let (centipawn_loss, judgement) = match (before.score, after.score) {
+            (Some(before), Some(after)) => judge_move(before, after, side),
+            // mating is never a mistake
+            _ => (0, None),
+        };
+
+        analysed.push(MoveAnalysis {
+            chess_move: *chess_move,
+            san: to_san(&boards[ply], *chess_move),
+            side,
+            eval: after.score,
+            best_move: before.best_move,
+            best_san: before.best_move.map(|m| to_san(&boards[ply], m)),
+            best_line: before.best_line.clone(),
+            centipawn_loss,
+            judgement,
+        });
+    }
+
+    Ok(GameAnalysis { moves: analysed })
+}
+
+async fn evaluate(
+    engine_handle: &mut EngineHandle,
+    record: &GameRecord,
+    moves: &[ChessMove],
+    board: &Board,
+) -> io::Result<PositionEval> {
+    match board.status() {
+        BoardStatus::Checkmate => {
+            return Ok(PositionEval {
+                score: None,
+                best_move: None,
+                best_line: Vec::new(),
+            });
+        }
+        BoardStatus::Stalemate => {
+            return Ok(PositionEval {
+                score: Some(Score::Centipawns(0)),
+                best_move: None,
+                best_line: Vec::new(),
+            });
+        }
+        BoardStatus::Ongoing => (),
+    };
+
+    let go = StockfishInput::Go {
+        start_fen: record.start().to_string(),
+        moves: moves.to_vec(),
+        time_control: None,
+    };
+    send(engine_handle, go).await?;
+
+    // the last evaluation sent before the best move is the one of the deepest search
+    let mut score = None;
+    let mut best_line = Vec::new();
+    loop {
+        match engine_handle.rx.recv().await {
+            Some(StockfishOutput::StockfishInfo { info }) if info.is_exact() => {
+                score = info.score;
+                best_line = info.pv;
+            }
+            Some(StockfishOutput::StockfishInfo { .. }) => (),
+            Some(StockfishOutput::StockfishBestMove { chess_move }) => {
+                debug!("Position {}: {:?}, best move {}", board, score, chess_move);
+                return Ok(PositionEval {
+                    score: score.map(|s| s.for_white(board.side_to_move())),
+                    best_move: Some(chess_move),
+                    best_line,
+                });
+            }
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "Stockfish stopped answering during the analysis",
+                ));
+            }
+        };
+    }
+}
+
+async fn send(engine_handle: &EngineHandle, input: StockfishInput) -> io::Result<()> {
+    engine_handle.tx.send(input).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "Stockfish stopped answering during the analysis",
+        )
+    })
+}
+
+/// The centipawns lost by the move of `side`, and how bad it is, from the scores
+/// (white's point of view) before and after it.
+fn judge_move(before: Score, after: Score, side: Color) -> (i32, Option<Judgement>) {
+    let (before, after) = (
+        relative_centipawns(before, side),
+        relative_centipawns(after, side),
+    );
+    let lost_chances = winning_chances(before) - winning_chances(after);
+    ((before - after).max(0), judge(lost_chances))
+}
+
+/// Centipawns from the point of view of `side`, capped so that winning by a lot
+/// and mating count the same.
+fn relative_centipawns(score: Score, side: Color) -> i32 {
+    let cp = score.to_centipawns().clamp(-1000, 1000);
+    match side {
+        Color::White => cp,
+        Color::Black => -cp,
+    }
+}
+
+/// Winning chances from -1 (lost) to 1 (won), the sigmoid used by Lichess.
+pub(crate) fn winning_chances(centipawns: i32) -> f64 {
+    2.0 / (1.0 + (-0.00368208 * f64::from(centipawns)).exp()) - 1.0
+}
+
+fn judge(lost_chances: f64) -> Option<Judgement> {
+    if lost_chances >= BLUNDER {
+        Some(Judgement::Blunder)
+    } else if lost_chances >= MISTAKE {
+        Some(Judgement::Mistake)
+    } else if lost_chances >= INACCURACY {
+        Some(Judgement::Inaccuracy)
+    } else {
+        None
+    }
+}
+
+impl Judgement {
+    pub(crate) fn symbol(self) -> &'static str {
+        match self {
+            Judgement::Inaccuracy => "?!",
+            Judgement::Mistake => "?",
+            Judgement::Blunder => "??",
+        }
+    }
+
+    /// The PGN Numeric Annotation Glyph of the symbol.
+    pub(crate) fn nag(self) -> &'static str {
+        match self {
+            Judgement::Inaccuracy => "$6",
+            Judgement::Mistake => "$2",
+            Judgement::Blunder => "$4",
+        }
+    }
+}
+
+impl GameAnalysis {
+    /// Print every inaccuracy, mistake and blunder, then the totals of each player.
+    pub(crate) fn print_summary(&self) {
+        println!("<<< Game analysis >>>");
+
+        for (ply, analysed) in self.moves.iter().enumerate() {
+            let Some(judgement) = analysed.judgement else {
+                continue;
+            };
+            let number = ply / 2 + 1;
+            let dots = if analysed.side == Color::White {
+                "."
+            } else {
+                "..."
+            };
+            let eval = analysed
+                .eval
+                .map_or(String::from("-"), |score| score.to_string());
+            let best = analysed
+                .best_san
+                .as_deref()
+                .map_or(String::from("-"), localize);
+            println!(
+                "{number}{dots} {}{} ({:?}) eval {eval}, best was {best}",
+                localize(&analysed.san),
+                judgement.symbol(),
+                judgement
+            );
+        }
+
+        for side in [Color::White, Color::Black] {
+            let moves: Vec<&MoveAnalysis> = self.moves.iter().filter(|m| m.side == side).collect();
+            let count = |judgement| {
+                moves
+                    .iter()
+                    .filter(|m| m.judgement == Some(judgement))
+                    .count()
+            };
+            let average_loss = match moves.len() {
+                0 => 0,
+                n => moves.iter().map(|m| m.centipawn_loss).sum::<i32>() / n as i32,
+            };
+            println!(
+                "{:?}: {} inaccuracies, {} mistakes, {} blunders, average centipawn loss {}",
+                side,
+                count(Judgement::Inaccuracy),
+                count(Judgement::Mistake),
+                count(Judgement::Blunder),
+                average_loss
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn winning_chances_are_symmetric_and_bounded() {
+        assert_eq!(winning_chances(0), 0.0);
+        assert!((winning_chances(300) + winning_chances(-300)).abs() < 1e-12);
+        assert!(winning_chances(1000) > 0.9 && winning_chances(1000) < 1.0);
+        assert!(winning_chances(-1000) < -0.9);
+    }
+
+    #[test]
+    fn judge_at_the_thresholds() {
+        assert_eq!(judge(0.0), None);
+        assert_eq!(judge(INACCURACY - 0.001), None);
+        assert_eq!(judge(INACCURACY), Some(Judgement::Inaccuracy));
+        assert_eq!(judge(MISTAKE - 0.001), Some(Judgement::Inaccuracy));
+        assert_eq!(judge(MISTAKE), Some(Judgement::Mistake));
+        assert_eq!(judge(BLUNDER - 0.001), Some(Judgement::Mistake));
+        assert_eq!(judge(BLUNDER), Some(Judgement::Blunder));
+        assert_eq!(judge(2.0), Some(Judgement::Blunder));
+    }
+
+    #[test]
+    fn relative_centipawns_flips_for_black_and_caps() {
+        assert_eq!(relative_centipawns(Score::Centipawns(50), Color::White), 50);
+        assert_eq!(
+            relative_centipawns(Score::Centipawns(50), Color::Black),
+            -50
+        );
+        assert_eq!(
+            relative_centipawns(Score::Centipawns(3000), Color::White),
+            1000
+        );
+        assert_eq!(relative_centipawns(Score::Mate(3), Color::Black), -1000);
+        assert_eq!(relative_centipawns(Score::Mate(-2), Color::Black), 1000);
+    }
+
+    #[test]
+    fn judge_move_of_each_side() {
+        // white's advantage grows from equal to +4: a blunder of black, a fine move of white
+        let (before, after) = (Score::Centipawns(0), Score::Centipawns(400));
+        assert_eq!(
+            judge_move(before, after, Color::Black),
+            (400, Some(Judgement::Blunder))
+        );
+        assert_eq!(judge_move(before, after, Color::White), (0, None));
+
+        // black lets a small edge slip: only an inaccuracy
+        let (before, after) = (Score::Centipawns(-60), Score::Centipawns(0));
+        assert_eq!(
+            judge_move(before, after, Color::Black),
+            (60, Some(Judgement::Inaccuracy))
+        );
+
+        // small losses are not judged
+        let (before, after) = (Score::Centipawns(-20), Score::Centipawns(0));
+        assert_eq!(judge_move(before, after, Color::Black), (20, None));
+    }
+}
@@ -0,0 +1,22 @@
+use log::info;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+// sub-directory of the archive directory with the monthly PGN files
+const GAMES_DIR: &str = "games";
+
+/// Append a finished game to the archive of the month, `games/<YYYY-MM>.pgn`.
+pub(crate) fn save_game(archive_dir: &Path, pgn: &str) -> io::Result<PathBuf> {
+    let games_dir = archive_dir.join(GAMES_DIR);
+    fs::create_dir_all(&games_dir)?;
+
+    let path = games_dir.join(format!("{}.pgn", chrono::Local::now().format("%Y-%m")));
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    // games are separated by an empty line
+    writeln!(file, "{}", pgn.trim_end())?;
+    writeln!(file)?;
+
+    info!("Game saved in {}", path.display());
+    Ok(path)
+}
@@ -0,0 +1,288 @@
+use chess::{ALL_FILES, ALL_RANKS, Board, ChessMove, File, MoveGen, Piece, Rank, Square};
+
+/// A button of the pad.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Key {
+    Piece(Piece),
+    File(File),
+    Rank(Rank),
+    Confirm,
+    Cancel,
+}
+
+impl Key {
+    /// The key for a character typed in the terminal, standing in for the pad:
+    /// `KQRBNP` for the pieces, `a` to `h` and `1` to `8` for the squares,
+    /// `!` to confirm and `<` to cancel.
+    pub(crate) fn from_char(c: char) -> Option<Key> {
+        let key = match c {
+            'K' => Key::Piece(Piece::King),
+            'Q' => Key::Piece(Piece::Queen),
+            'R' => Key::Piece(Piece::Rook),
+            'B' => Key::Piece(Piece::Bishop),
+            'N' => Key::Piece(Piece::Knight),
+            'P' => Key::Piece(Piece::Pawn),
+            'a'..='h' => Key::File(ALL_FILES[c as usize - 'a' as usize]),
+            '1'..='8' => Key::Rank(ALL_RANKS[c as usize - '1' as usize]),
+            '!' => Key::Confirm,
+            '<' => Key::Cancel,
+            _ => return None,
+        };
+        Some(key)
+    }
+}
+
+/// The keys of a line typed in the terminal, `None` if it isn't made of keys only.
+pub(crate) fn keys_of(line: &str) -> Option<Vec<Key>> {
+    if line.is_empty() {
+        return None;
+    }
+    line.chars().map(Key::from_char).collect()
+}
+
+/// Where the entry of a move stands after a key press.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Entry {
+    /// more keys are needed, the text says which ones
+    NeedKey(String),
+    /// only one move is left, `Confirm` plays it
+    Ready(ChessMove),
+    /// the move was confirmed, the entry starts over
+    Move(ChessMove),
+    /// the key doesn't fit here and was ignored, the text says why
+    Rejected(String),
+}
+
+/// Turns the key presses of the pad into a legal move of `board`: the piece (none for a pawn),
+/// the file and rank of the destination, then the file or rank of the piece and the promotion
+/// only when several moves are left, and `Confirm`. `Cancel` starts over.
+#[derive(Debug, Clone)]
+pub(crate) struct MoveEntry {
+    board: Board,
+    piece: Piece,
+    dest_file: Option<File>,
+    /// the legal moves left, once the destination is known
+    candidates: Vec<ChessMove>,
+}
+
+impl MoveEntry {
+    pub(crate) fn new(board: Board) -> MoveEntry {
+        MoveEntry {
+            board,
+            piece: Piece::Pawn,
+            dest_file: None,
+            candidates: Vec::new(),
+        }
+    }
+
+    pub(crate) fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub(crate) fn feed(&mut self, key: Key) -> Entry {
+        match key {
+            Key::Cancel => {
+                *self = MoveEntry::new(self.board);
+                self.next_step()
+            }
+            Key::Confirm => match self.chosen() {
+                Some(chess_move) => {
+                    *self = MoveEntry::new(self.board);
+                    Entry::Move(chess_move)
+                }
+                None => match self.next_step() {
+                    Entry::NeedKey(text) => Entry::Rejected(format!("Not finished: {text}")),
+                    entry => entry,
+                },
+            },
+            _ if self.candidates.is_empty() => self.feed_destination(key),
+            _ => self.feed_choice(key),
+        }
+    }
+
+    /// Keys before the destination is known: the piece, then the file and rank.
+    fn feed_destination(&mut self, key: Key) -> Entry {
+        match key {
+            Key::Piece(piece) if self.dest_file.is_none() => {
+                self.piece = piece;
+                self.next_step()
+            }
+            // another file corrects the previous one
+            Key::File(file) => {
+                self.dest_file = Some(file);
+                self.next_step()
+            }
+            Key::Rank(rank) => {
+                let Some(file) = self.dest_file else {
+                    return Entry::Rejected("Press the file of the destination first".to_string());
+                };
+                let dest = Square::make_square(rank, file);
+                let candidates: Vec<ChessMove> = MoveGen::new_legal(&self.board)
+                    .filter(|m| m.get_dest() == dest)
+                    .filter(|m| self.board.piece_on(m.get_source()) == Some(self.piece))
+                    .collect();
+                if candidates.is_empty() {
+                    self.dest_file = None;
+                    return Entry::Rejected(format!("No {:?} can go to {dest}", self.piece));
+                }
+                self.candidates = candidates;
+                self.next_step()
+            }
+            _ => Entry::Rejected("Press the rank of the destination".to_string()),
+        }
+    }
+
+    /// Keys after the destination: the file or rank of the piece, or the promotion,
+    /// keeping only the moves that match.
+    fn feed_choice(&mut self, key: Key) -> Entry {
+        let remaining: Vec<ChessMove> = self
+            .candidates
+            .iter()
+            .copied()
+            .filter(|m| match key {
+                Key::File(file) => m.get_source().get_file() == file,
+                Key::Rank(rank) => m.get_source().get_rank() == rank,
+                Key::Piece(piece) => m.get_promotion() == Some(piece),
+                Key::Confirm | Key::Cancel => true,
+            })
+            .collect();
+        if remaining.is_empty() {
+            return Entry::Rejected(format!("{key:?} matches none of the moves left"));
+        }
+        self.candidates = remaining;
+        self.next_step()
+    }
+
+    /// What to press next, or the move if only one is left.
+    fn next_step(&self) -> Entry {
+        if let Some(chess_move) = self.chosen() {
+            return Entry::Ready(chess_move);
+        }
+        let Some(first) = self.candidates.first() else {
+            let text = match self.dest_file {
+                None => {
+                    "Press the piece (none for a pawn), then the file and rank of the destination"
+                }
+                Some(_) => "Press the rank of the destination",
+            };
+            return Entry::NeedKey(text.to_string());
+        };
+
+        if self
+            .candidates
+            .iter()
+            .any(|m| m.get_source() != first.get_source())
+        {
+            Entry::NeedKey(format!(
+                "Several pieces can go to {}: press the file or rank of the one to move",
+                first.get_dest()
+            ))
+        } else {
+            Entry::NeedKey("Press the piece to promote to".to_string())
+        }
+    }
+
+    /// The move entered, once only one is left.
+    fn chosen(&self) -> Option<ChessMove> {
+        match self.candidates.as_slice() {
+            [chess_move] => Some(*chess_move),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// Press the keys typed in `keys`, returning where the entry stands after the last one.
+    fn press(entry: &mut MoveEntry, keys: &str) -> Entry {
+        let keys = keys_of(keys).expect("Only keys in the test");
+        keys.into_iter().map(|key| entry.feed(key)).last().unwrap()
+    }
+
+    fn entry(fen: &str) -> MoveEntry {
+        MoveEntry::new(Board::from_str(fen).unwrap())
+    }
+
+    #[test]
+    fn unique_move_is_ready_then_played() {
+        let mut entry = MoveEntry::new(Board::default());
+        let e4 = ChessMove::new(Square::E2, Square::E4, None);
+        assert_eq!(press(&mut entry, "e4"), Entry::Ready(e4));
+        assert_eq!(entry.feed(Key::Confirm), Entry::Move(e4));
+        // the entry starts over
+        assert!(matches!(entry.feed(Key::Confirm), Entry::Rejected(_)));
+    }
+
+    #[test]
+    fn knights_to_the_same_square_ask_for_the_file_then_the_rank() {
+        // knights on b1, f1 and b3 can all go to d2
+        let mut entry = entry("4k3/8/8/8/8/1N6/8/1N2KN2 w - - 0 1");
+        assert!(matches!(press(&mut entry, "Nd2"), Entry::NeedKey(_)));
+        assert!(matches!(press(&mut entry, "b"), Entry::NeedKey(_)));
+        assert_eq!(
+            press(&mut entry, "3"),
+            Entry::Ready(ChessMove::new(Square::B3, Square::D2, None))
+        );
+    }
+
+    #[test]
+    fn pawns_capturing_to_promote_ask_for_the_file_then_the_promotion() {
+        let mut entry = entry("2r4k/1P1P4/8/8/8/8/8/4K3 w - - 0 1");
+        assert!(matches!(press(&mut entry, "c8"), Entry::NeedKey(_)));
+        assert_eq!(
+            press(&mut entry, "d"),
+            Entry::NeedKey("Press the piece to promote to".to_string())
+        );
+        assert_eq!(
+            press(&mut entry, "N"),
+            Entry::Ready(ChessMove::new(Square::D7, Square::C8, Some(Piece::Knight)))
+        );
+    }
+
+    #[test]
+    fn cancel_starts_over() {
+        let mut entry = MoveEntry::new(Board::default());
+        press(&mut entry, "Nf");
+        assert_eq!(
+            entry.feed(Key::Cancel),
+            Entry::NeedKey(
+                "Press the piece (none for a pawn), then the file and rank of the destination"
+                    .to_string()
+            )
+        );
+        // back to a pawn move
+        assert_eq!(
+            press(&mut entry, "f3"),
+            Entry::Ready(ChessMove::new(Square::F2, Square::F3, None))
+        );
+    }
+
+    #[test]
+    fn rank_before_file_is_rejected() {
+        let mut entry = MoveEntry::new(Board::default());
+        assert_eq!(
+            press(&mut entry, "4"),
+            Entry::Rejected("Press the file of the destination first".to_string())
+        );
+    }
+
+    #[test]
+    fn unreachable_destination_clears_the_file() {
+        let mut entry = MoveEntry::new(Board::default());
+        assert_eq!(
+            press(&mut entry, "e5"),
+            Entry::Rejected("No Pawn can go to e5".to_string())
+        );
+        assert_eq!(
+            press(&mut entry, "4"),
+            Entry::Rejected("Press the file of the destination first".to_string())
+        );
+        assert_eq!(
+            press(&mut entry, "e4"),
+            Entry::Ready(ChessMove::new(Square::E2, Square::E4, None))
+        );
+    }
+}
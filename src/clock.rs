@@ -0,0 +1,472 @@
+use chess::Color;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// What happens to a player's time when they move.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Increment {
+    None,
+    /// time added after every move
+    Fischer(Duration),
+    /// the clock only starts running once the delay is over (simple or US delay)
+    Delay(Duration),
+    /// the time used for the move is given back, up to the delay
+    Bronstein(Duration),
+}
+
+/// One stage of a time control, e.g. 40 moves in 90 minutes with 30 seconds increment.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TimeControlStage {
+    /// number of moves to play in this stage, `None` for the rest of the game
+    pub(crate) moves: Option<u32>,
+    pub(crate) time: Duration,
+    pub(crate) increment: Increment,
+}
+
+/// A time control made of one or more stages, e.g. `40/90+30:30+30`.
+/// When the last stage has a number of moves, it is repeated.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TimeControl {
+    pub(crate) stages: Vec<TimeControlStage>,
+}
+
+/// The flag of this side fell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Flag(pub(crate) Color);
+
+impl TimeControl {
+    /// A single stage time control with a Fischer increment, like 5+3.
+    pub(crate) fn fischer(minutes: u32, increment: u32) -> TimeControl {
+        let increment = match increment {
+            0 => Increment::None,
+            seconds => Increment::Fischer(Duration::from_secs(seconds.into())),
+        };
+        TimeControl {
+            stages: vec![TimeControlStage {
+                moves: None,
+                time: Duration::from_secs(u64::from(minutes) * 60),
+                increment,
+            }],
+        }
+    }
+
+    /// The time control as written in the PGN `TimeControl` tag, e.g. `40/5400+30:1800+30`.
+    /// PGN has no notation for delays, they are left out.
+    pub(crate) fn pgn_tag(&self) -> String {
+        let stages: Vec<String> = self
+            .stages
+            .iter()
+            .map(|stage| {
+                let mut tag = String::new();
+                if let Some(moves) = stage.moves {
+                    tag.push_str(&format!("{moves}/"));
+                }
+                tag.push_str(&stage.time.as_secs().to_string());
+                if let Increment::Fischer(increment) = stage.increment {
+                    tag.push_str(&format!("+{}", increment.as_secs()));
+                }
+                tag
+            })
+            .collect();
+        stages.join(":")
+    }
+
+    /// A single stage time control from the clock of a Lichess game.
+    pub(crate) fn from_lichess_clock(initial: Duration, increment: Duration) -> TimeControl {
+        let increment = if increment.is_zero() {
+            Increment::None
+        } else {
+            Increment::Fischer(increment)
+        };
+        TimeControl {
+            stages: vec![TimeControlStage {
+                moves: None,
+                time: initial,
+                increment,
+            }],
+        }
+    }
+
+    /// The clock as Lichess wants it, in seconds: initial time and increment.
+    /// `None` if Lichess can't play it (several stages or a delay).
+    pub(crate) fn lichess_clock(&self) -> Option<(u32, u32)> {
+        let [stage] = self.stages.as_slice() else {
+            return None;
+        };
+        if stage.moves.is_some() {
+            return None;
+        }
+        let increment = match stage.increment {
+            Increment::None => 0,
+            Increment::Fischer(increment) => increment.as_secs() as u32,
+            Increment::Delay(_) | Increment::Bronstein(_) => return None,
+        };
+        Some((stage.time.as_secs() as u32, increment))
+    }
+}
+
+impl FromStr for TimeControl {
+    type Err = String;
+
+    /// Stages are separated by `:`, each one written `[moves/]minutes[+|d|b seconds]`:
+    /// `+` for a Fischer increment, `d` for a simple delay, `b` for a Bronstein delay.
+    /// Examples: `5+3`, `15d5`, `40/90+30:30+30`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut stages = Vec::new();
+
+        for stage in s.trim().split(':') {
+            let (moves, rest) = match stage.split_once('/') {
+                Some((moves, rest)) => {
+                    let moves = match moves.parse() {
+                        Ok(0) | Err(_) => return Err(format!("Invalid number of moves: {moves}")),
+                        Ok(moves) => moves,
+                    };
+                    (Some(moves), rest)
+                }
+                None => (None, stage),
+            };
+
+            let (minutes, increment) = match rest.find(['+', 'd', 'b']) {
+                Some(index) => {
+                    let (minutes, increment) = rest.split_at(index);
+                    let seconds: u64 = increment[1..]
+                        .parse()
+                        .map_err(|_| format!("Invalid increment: {increment}"))?;
+                    let seconds = Duration::from_secs(seconds);
+                    let increment = match &increment[..1] {
+                        "+" => Increment::Fischer(seconds),
+                        "d" => Increment::Delay(seconds),
+                        _ => Increment::Bronstein(seconds),
+                    };
+                    (minutes, increment)
+                }
+                None => (rest, Increment::None),
+            };
+
+            let minutes: f64 = minutes
+                .parse()
+                .map_err(|_| format!("Invalid number of minutes: {minutes}"))?;
+            if !minutes.is_finite() || minutes <= 0.0 {
+                return Err(format!("Invalid number of minutes: {minutes}"));
+            }
+
+            stages.push(TimeControlStage {
+                moves,
+                time: Duration::from_secs_f64(minutes * 60.0),
+                increment,
+            });
+        }
+
+        Ok(TimeControl { stages })
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            if let Some(moves) = stage.moves {
+                write!(f, "{moves}/")?;
+            }
+            write!(f, "{}", stage.time.as_secs_f64() / 60.0)?;
+            match stage.increment {
+                Increment::None => (),
+                Increment::Fischer(d) => write!(f, "+{}", d.as_secs())?,
+                Increment::Delay(d) => write!(f, "d{}", d.as_secs())?,
+                Increment::Bronstein(d) => write!(f, "b{}", d.as_secs())?,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// A chess clock: the time left for both sides, and whose clock is running.
+#[derive(Debug, Clone)]
+pub(crate) struct Clock {
+    control: TimeControl,
+    remaining: [Duration; 2],
+    /// current stage of each side, and moves played in that stage
+    stage: [usize; 2],
+    stage_moves: [u32; 2],
+    running: Option<(Color, Instant)>,
+}
+
+impl Clock {
+    pub(crate) fn new(control: TimeControl) -> Clock {
+        let time = control.stages[0].time;
+        Clock {
+            control,
+            remaining: [time; 2],
+            stage: [0; 2],
+            stage_moves: [0; 2],
+            running: None,
+        }
+    }
+
+    /// Start the clock of the given side.
+    pub(crate) fn start(&mut self, color: Color) {
+        self.running = Some((color, Instant::now()));
+    }
+
+    /// Stop both clocks, e.g. when the game is over.
+    pub(crate) fn stop(&mut self) {
+        if let Some((color, started)) = self.running.take() {
+            let used = self.used(color, started.elapsed());
+            self.remaining[color.to_index()] =
+                self.remaining[color.to_index()].saturating_sub(used);
+        }
+    }
+
+    /// Time left for the given side, accounting for the time spent if its clock is running.
+    pub(crate) fn remaining(&self, color: Color) -> Duration {
+        let remaining = self.remaining[color.to_index()];
+        match self.running {
+            Some((running, started)) if running == color => {
+                remaining.saturating_sub(self.used(color, started.elapsed()))
+            }
+            _ => remaining,
+        }
+    }
+
+    /// The side whose clock is running, if any.
+    pub(crate) fn running(&self) -> Option<Color> {
+        self.running.map(|(color, _)| color)
+    }
+
+    /// Set the time left for both sides from an outside source, e.g. the Lichess server,
+    /// and start the clock of `running` (`None` to stop both).
+    pub(crate) fn sync(&mut self, white: Duration, black: Duration, running: Option<Color>) {
+        self.remaining = [white, black];
+        self.running = running.map(|color| (color, Instant::now()));
+    }
+
+    /// The side whose flag fell, if any.
+    pub(crate) fn flagged(&self) -> Option<Color> {
+        match self.running {
+            Some((color, _)) if self.remaining(color).is_zero() => Some(color),
+            _ => None,
+        }
+    }
+
+    /// How long the running side can still think before its flag falls.
+    pub(crate) fn time_until_flag(&self) -> Option<Duration> {
+        let (color, started) = self.running?;
+        let delay = match self.increment(color) {
+            Increment::Delay(delay) => delay.saturating_sub(started.elapsed()),
+            _ => Duration::ZERO,
+        };
+        Some(self.remaining(color) + delay)
+    }
+
+    /// The running side finished its move: stop its clock, apply the increment or
+    /// delay, and start the opponent's clock. Returns the time left for the side that moved.
+    pub(crate) fn press(&mut self) -> Result<Duration, Flag> {
+        let Some((color, started)) = self.running else {
+            panic!("Clock pressed while not running");
+        };
+        let index = color.to_index();
+        let elapsed = started.elapsed();
+        let used = self.used(color, elapsed);
+
+        if used >= self.remaining[index] {
+            self.remaining[index] = Duration::ZERO;
+            self.running = None;
+            return Err(Flag(color));
+        }
+        self.remaining[index] -= used;
+
+        match self.increment(color) {
+            Increment::Fischer(increment) => self.remaining[index] += increment,
+            Increment::Bronstein(delay) => self.remaining[index] += elapsed.min(delay),
+            Increment::Delay(_) | Increment::None => (),
+        };
+
+        // move on to the next stage once the moves of this one are played
+        let stage = &self.control.stages[self.stage[index]];
+        self.stage_moves[index] += 1;
+        if stage.moves == Some(self.stage_moves[index]) {
+            self.stage_moves[index] = 0;
+            self.stage[index] = (self.stage[index] + 1).min(self.control.stages.len() - 1);
+            self.remaining[index] += self.control.stages[self.stage[index]].time;
+        }
+
+        let remaining = self.remaining[index];
+        self.running = Some((!color, Instant::now()));
+        Ok(remaining)
+    }
+
+    /// Moves left for this side before the next stage of the time control, if any.
+    pub(crate) fn moves_to_go(&self, color: Color) -> Option<u32> {
+        let index = color.to_index();
+        let moves = self.control.stages[self.stage[index]].moves?;
+        Some(moves - self.stage_moves[index])
+    }
+
+    pub(crate) fn increment(&self, color: Color) -> Increment {
+        self.control.stages[self.stage[color.to_index()]].increment
+    }
+
+    /// Time taken from the clock for a move that lasted `elapsed`.
+    fn used(&self, color: Color, elapsed: Duration) -> Duration {
+        match self.increment(color) {
+            Increment::Delay(delay) => elapsed.saturating_sub(delay),
+            _ => elapsed,
+        }
+    }
+}
+
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "White {} - Black {}",
+            format_duration(self.remaining(Color::White)),
+            format_duration(self.remaining(Color::Black))
+        )
+    }
+}
+
+/// Format a duration as H:MM:SS, the format of PGN `%clk` comments.
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn parses_a_fischer_time_control() {
+        let control: TimeControl = "5+3".parse().unwrap();
+        assert_eq!(
+            control.stages,
+            vec![TimeControlStage {
+                moves: None,
+                time: minutes(5),
+                increment: Increment::Fischer(Duration::from_secs(3)),
+            }]
+        );
+        assert_eq!(control.pgn_tag(), "300+3");
+    }
+
+    #[test]
+    fn parses_delays_and_stages() {
+        let control: TimeControl = "15d5".parse().unwrap();
+        assert_eq!(
+            control.stages[0].increment,
+            Increment::Delay(Duration::from_secs(5))
+        );
+        let control: TimeControl = "15b5".parse().unwrap();
+        assert_eq!(
+            control.stages[0].increment,
+            Increment::Bronstein(Duration::from_secs(5))
+        );
+
+        let control: TimeControl = "40/90+30:30+30".parse().unwrap();
+        assert_eq!(control.stages.len(), 2);
+        assert_eq!(control.stages[0].moves, Some(40));
+        assert_eq!(control.stages[0].time, minutes(90));
+        assert_eq!(control.stages[1].moves, None);
+        assert_eq!(control.stages[1].time, minutes(30));
+        assert_eq!(control.pgn_tag(), "40/5400+30:1800+30");
+        assert_eq!(control.to_string(), "40/90+30:30+30");
+    }
+
+    #[test]
+    fn parses_fractions_of_minutes() {
+        let control: TimeControl = "0.5".parse().unwrap();
+        assert_eq!(control.stages[0].time, Duration::from_secs(30));
+        assert_eq!(control.stages[0].increment, Increment::None);
+        let control: TimeControl = "1.5+1".parse().unwrap();
+        assert_eq!(control.stages[0].time, Duration::from_secs(90));
+    }
+
+    #[test]
+    fn rejects_invalid_time_controls() {
+        for invalid in [
+            "",
+            "abc",
+            "d5",
+            "5+x",
+            "0",
+            "-3+2",
+            "x/5+3",
+            "0/5+3",
+            "90/40+30,30+30",
+        ] {
+            assert!(
+                invalid.parse::<TimeControl>().is_err(),
+                "{invalid} should be invalid"
+            );
+        }
+    }
+
+    #[test]
+    fn press_adds_the_fischer_increment() {
+        let mut clock = Clock::new("5+3".parse().unwrap());
+        clock.start(Color::White);
+        let remaining = clock.press().unwrap();
+        assert!(remaining > minutes(5));
+        assert!(remaining <= minutes(5) + Duration::from_secs(3));
+        assert_eq!(clock.running(), Some(Color::Black));
+        assert_eq!(clock.remaining(Color::White), remaining);
+    }
+
+    #[test]
+    fn press_within_the_delay_uses_no_time() {
+        let mut clock = Clock::new("15d5".parse().unwrap());
+        clock.start(Color::White);
+        assert_eq!(clock.press(), Ok(minutes(15)));
+        assert!(clock.time_until_flag().unwrap() > minutes(15));
+    }
+
+    #[test]
+    fn press_gives_back_the_time_used_up_to_the_bronstein_delay() {
+        let mut clock = Clock::new("15b5".parse().unwrap());
+        clock.start(Color::White);
+        assert_eq!(clock.press(), Ok(minutes(15)));
+    }
+
+    #[test]
+    fn press_moves_on_to_the_next_stage() {
+        let mut clock = Clock::new("2/10:5".parse().unwrap());
+        clock.start(Color::White);
+        assert_eq!(clock.moves_to_go(Color::White), Some(2));
+
+        clock.press().unwrap();
+        assert_eq!(clock.moves_to_go(Color::White), Some(1));
+        clock.press().unwrap();
+        let remaining = clock.press().unwrap();
+        assert_eq!(clock.moves_to_go(Color::White), None);
+        assert!(remaining > minutes(14));
+        assert!(remaining <= minutes(15));
+    }
+
+    #[test]
+    fn press_after_the_flag_fell() {
+        let mut clock = Clock::new("5+3".parse().unwrap());
+        clock.sync(Duration::ZERO, minutes(5), Some(Color::White));
+        assert_eq!(clock.flagged(), Some(Color::White));
+        assert_eq!(clock.press(), Err(Flag(Color::White)));
+        assert_eq!(clock.running(), None);
+    }
+
+    #[test]
+    fn formats_durations_like_pgn_clocks() {
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+        assert_eq!(format_duration(Duration::from_millis(59_900)), "0:00:59");
+    }
+}
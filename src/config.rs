@@ -0,0 +1,238 @@
+use crate::stockfish::{EngineStrength, SearchLimit};
+
+use serde::Deserialize;
+use std::io;
+use std::path::PathBuf;
+
+// environment variables overriding the config file
+const CONFIG_PATH_ENV: &str = "MINAC_CONFIG";
+const LICHESS_TOKEN_ENV: &str = "MINAC_LICHESS_TOKEN";
+const STOCKFISH_PATH_ENV: &str = "MINAC_STOCKFISH_PATH";
+const STOCKFISH_ARGS_ENV: &str = "MINAC_STOCKFISH_ARGS";
+
+/// The minac configuration, read from `~/.config/minac/config.toml`.
+/// Every field has a default, so a missing file or section is not an error.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct Config {
+    pub(crate) player: PlayerConfig,
+    pub(crate) lichess: LichessConfig,
+    pub(crate) engine: EngineConfig,
+    pub(crate) time_control: TimeControlConfig,
+    pub(crate) analysis: AnalysisConfig,
+    pub(crate) archive: ArchiveConfig,
+    pub(crate) input: InputConfig,
+    pub(crate) notation: NotationConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct PlayerConfig {
+    /// our name in the PGN of offline games
+    pub(crate) name: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct LichessConfig {
+    pub(crate) token: Option<String>,
+    /// study in which finished offline games are imported as chapters
+    pub(crate) study_id: Option<String>,
+    /// chapters are named "<prefix> <timestamp>"
+    pub(crate) chapter_prefix: String,
+}
+
+impl Default for LichessConfig {
+    fn default() -> Self {
+        LichessConfig {
+            token: None,
+            study_id: None,
+            chapter_prefix: String::from("Minac"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct EngineConfig {
+    /// engine binary, looked up in $PATH when not set
+    pub(crate) path: Option<PathBuf>,
+    pub(crate) args: Vec<String>,
+    pub(crate) skill_level: i64,
+    /// limit the engine strength to this rating instead of using the skill level
+    pub(crate) elo: Option<u32>,
+    /// search limit per move: the first one set among depth, nodes and movetime (in ms)
+    pub(crate) depth: Option<u8>,
+    pub(crate) nodes: Option<u64>,
+    pub(crate) movetime: Option<u64>,
+    pub(crate) threads: u32,
+    /// hash table size in MB
+    pub(crate) hash: u32,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            path: None,
+            args: Vec::new(),
+            skill_level: 1,
+            elo: None,
+            depth: Some(5),
+            nodes: None,
+            movetime: None,
+            threads: 1,
+            hash: 16,
+        }
+    }
+}
+
+impl EngineConfig {
+    pub(crate) fn strength(&self) -> EngineStrength {
+        match self.elo {
+            Some(elo) => EngineStrength::Elo(i64::from(elo)),
+            None => EngineStrength::SkillLevel(self.skill_level),
+        }
+    }
+
+    pub(crate) fn search_limit(&self) -> SearchLimit {
+        if let Some(depth) = self.depth {
+            SearchLimit::Depth(depth)
+        } else if let Some(nodes) = self.nodes {
+            SearchLimit::Nodes(nodes)
+        } else {
+            SearchLimit::MoveTime(self.movetime.unwrap_or(1000))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct TimeControlConfig {
+    pub(crate) minutes: u32,
+    pub(crate) increment: u32,
+}
+
+impl Default for TimeControlConfig {
+    fn default() -> Self {
+        TimeControlConfig {
+            minutes: 10,
+            increment: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct AnalysisConfig {
+    /// depth of the search for every position of the analysed game
+    pub(crate) depth: u8,
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        AnalysisConfig { depth: 14 }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct ArchiveConfig {
+    /// where finished games are saved, and queued before being uploaded to the study
+    pub(crate) dir: PathBuf,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        ArchiveConfig { dir: data_dir() }
+    }
+}
+
+/// Where moves and commands come from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum InputKind {
+    #[default]
+    Terminal,
+    /// lines replayed from a file, then minac exits
+    Script,
+    /// key presses of the button pad
+    Buttons,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct InputConfig {
+    pub(crate) source: InputKind,
+    /// the file replayed by the script source, one move or command per line
+    pub(crate) script: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub(crate) struct NotationConfig {
+    /// letters of the king, queen, rook, bishop and knight, e.g. "RDTFC" in French
+    pub(crate) piece_letters: String,
+}
+
+impl Default for NotationConfig {
+    fn default() -> Self {
+        NotationConfig {
+            piece_letters: String::from("KQRBN"),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file if it exists, then apply the environment variable overrides.
+    pub(crate) fn load() -> io::Result<Config> {
+        let mut config = match config_path() {
+            Some(path) if path.is_file() => {
+                let content = std::fs::read_to_string(&path)?;
+                toml::from_str(&content).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Invalid config file {}: {e}", path.display()),
+                    )
+                })?
+            }
+            _ => Config::default(),
+        };
+
+        if let Ok(token) = std::env::var(LICHESS_TOKEN_ENV) {
+            config.lichess.token = Some(token);
+        }
+        if let Some(path) = std::env::var_os(STOCKFISH_PATH_ENV) {
+            config.engine.path = Some(PathBuf::from(path));
+        }
+        if let Ok(args) = std::env::var(STOCKFISH_ARGS_ENV) {
+            config.engine.args = args.split_whitespace().map(String::from).collect();
+        }
+
+        Ok(config)
+    }
+}
+
+/// `$MINAC_CONFIG`, or `config.toml` in the `minac` folder of the user's config directory.
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os(CONFIG_PATH_ENV) {
+        return Some(PathBuf::from(path));
+    }
+
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("minac").join("config.toml"))
+}
+
+/// The `minac` folder of the user's data directory, `~/.local/share/minac` by default.
+fn data_dir() -> PathBuf {
+    let data_dir = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => match std::env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".local").join("share"),
+            None => PathBuf::from("."),
+        },
+    };
+    data_dir.join("minac")
+}
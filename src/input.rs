@@ -0,0 +1,298 @@
+use crate::buttons::{Entry, Key, MoveEntry, keys_of};
+use crate::config::{InputConfig, InputKind};
+use crate::notation::{localize, to_san, to_uci};
+
+use chess::Board;
+use futures::future::{BoxFuture, FutureExt};
+use log::{debug, error};
+use std::collections::VecDeque;
+use std::io::{self, Write, stdout};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use tokio::io::{AsyncBufReadExt, BufReader, stdin};
+use tokio::runtime::Handle;
+use tokio::sync::Mutex;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::task::block_in_place;
+use tokio::time::{Duration, sleep, timeout};
+
+// pause before each scripted line, so that the game can be followed
+const SCRIPT_DELAY: Duration = Duration::from_millis(500);
+
+/// Where the lines of input come from: moves, commands and answers to the prompts.
+pub(crate) trait InputSource: Send + Sync {
+    /// The next trimmed line, `None` once the source is exhausted.
+    /// Must be cancel safe: it is used in `tokio::select!`, no line may be lost
+    /// when another branch wins.
+    fn next_line(&self) -> BoxFuture<'_, Option<String>>;
+
+    /// A move is expected in this position, or none anymore.
+    /// Only needed by the sources that build the moves themselves, like the button pad.
+    fn set_position(&self, _position: Option<Board>) {}
+}
+
+/// Lines typed in the terminal, read by their own task: waiting for input can then time out
+/// or be interrupted by game events without losing what is typed afterwards.
+pub(crate) struct Terminal {
+    lines: Mutex<UnboundedReceiver<String>>,
+}
+
+impl Terminal {
+    /// Start reading stdin. Must be called from the tokio runtime.
+    pub(crate) fn new() -> Terminal {
+        let (tx, rx) = unbounded_channel();
+        tokio::spawn(forward_stdin(tx));
+        Terminal {
+            lines: Mutex::new(rx),
+        }
+    }
+}
+
+impl InputSource for Terminal {
+    fn next_line(&self) -> BoxFuture<'_, Option<String>> {
+        async move {
+            let line = self.lines.lock().await.recv().await?;
+            Some(line.trim().to_string())
+        }
+        .boxed()
+    }
+}
+
+/// Lines replayed from a file, for demos and integration tests.
+/// Empty lines and comments starting with `#` are skipped.
+pub(crate) struct Script {
+    lines: std::sync::Mutex<VecDeque<String>>,
+}
+
+impl Script {
+    pub(crate) fn load(path: &Path) -> io::Result<Script> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(Script::from_lines(content.lines().map(String::from)))
+    }
+
+    pub(crate) fn from_lines(lines: impl IntoIterator<Item = String>) -> Script {
+        let lines = lines
+            .into_iter()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        Script {
+            lines: std::sync::Mutex::new(lines),
+        }
+    }
+}
+
+impl InputSource for Script {
+    fn next_line(&self) -> BoxFuture<'_, Option<String>> {
+        async move {
+            // nothing is taken before the pause is over: cancelling loses no line
+            sleep(SCRIPT_DELAY).await;
+            let line = self.lines.lock().unwrap().pop_front()?;
+            // show the line as if it was typed
+            println!("{line}");
+            Some(line)
+        }
+        .boxed()
+    }
+}
+
+/// Lines entered on the button pad. The driver of the buttons gets a `PadDriver` from `driver()`.
+pub(crate) struct ButtonPad {
+    driver: PadDriver,
+    lines: Mutex<UnboundedReceiver<String>>,
+}
+
+impl ButtonPad {
+    pub(crate) fn new() -> ButtonPad {
+        let (tx, rx) = unbounded_channel();
+        ButtonPad {
+            driver: PadDriver {
+                tx,
+                entry: Arc::new(std::sync::Mutex::new(None)),
+            },
+            lines: Mutex::new(rx),
+        }
+    }
+
+    /// What the driver of the buttons uses to send the keys pressed.
+    pub(crate) fn driver(&self) -> PadDriver {
+        self.driver.clone()
+    }
+}
+
+impl InputSource for ButtonPad {
+    fn next_line(&self) -> BoxFuture<'_, Option<String>> {
+        async move {
+            let line = self.lines.lock().await.recv().await?;
+            Some(line.trim().to_string())
+        }
+        .boxed()
+    }
+
+    fn set_position(&self, position: Option<Board>) {
+        let mut entry = self.driver.entry.lock().unwrap();
+        // same position: keep the keys already pressed
+        if entry.as_ref().map(MoveEntry::board) != position.as_ref() {
+            *entry = position.map(MoveEntry::new);
+        }
+    }
+}
+
+/// Sends the keys pressed on the pad: they are turned into a move while one is expected,
+/// which is then sent as a line in UCI.
+#[derive(Clone)]
+pub(crate) struct PadDriver {
+    tx: UnboundedSender<String>,
+    /// the move being entered, `None` while no move is expected
+    entry: Arc<std::sync::Mutex<Option<MoveEntry>>>,
+}
+
+impl PadDriver {
+    /// Press a key. Returns false if no move is expected: the key is ignored.
+    pub(crate) fn press(&self, key: Key) -> bool {
+        let mut entry = self.entry.lock().unwrap();
+        let Some(entry) = entry.as_mut() else {
+            return false;
+        };
+        match entry.feed(key) {
+            Entry::NeedKey(text) | Entry::Rejected(text) => println!("{text}"),
+            Entry::Ready(chess_move) => {
+                let san = to_san(entry.board(), chess_move);
+                println!("{}: confirm to play it", localize(&san));
+            }
+            Entry::Move(chess_move) => {
+                let _ = self.tx.send(to_uci(chess_move));
+            }
+        };
+        true
+    }
+
+    /// Whether the keys pressed now make a move.
+    pub(crate) fn expects_move(&self) -> bool {
+        self.entry.lock().unwrap().is_some()
+    }
+
+    /// Send a whole line, e.g. a command or the answer to a question.
+    pub(crate) fn send_line(&self, line: String) {
+        let _ = self.tx.send(line);
+    }
+}
+
+/// The input source chosen in the config. Must be called from the tokio runtime.
+pub(crate) fn from_config(input: &InputConfig) -> io::Result<Box<dyn InputSource>> {
+    match input.source {
+        InputKind::Terminal => Ok(Box::new(Terminal::new())),
+        InputKind::Script => {
+            let path = input.script.as_ref().ok_or(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The script input needs a script file",
+            ))?;
+            Ok(Box::new(Script::load(path)?))
+        }
+        InputKind::Buttons => {
+            let pad = ButtonPad::new();
+            // no driver for the buttons yet: what is typed in the terminal stands in for them
+            tokio::spawn(drive_pad_from_stdin(pad.driver()));
+            Ok(Box::new(pad))
+        }
+    }
+}
+
+/// Use this input source from now on. Only the first call has an effect.
+pub(crate) fn set_source(input: Box<dyn InputSource>) {
+    if SOURCE.set(input).is_err() {
+        debug!("Input source already set, keeping it");
+    }
+}
+
+/// Tell the input source a move is expected in this position, or none anymore.
+pub(crate) fn set_position(position: Option<Board>) {
+    source().set_position(position);
+}
+
+/// Print the prompt, and wait for the next line of input, giving up after `within`.
+pub(crate) async fn read_line_within(within: Option<Duration>) -> Option<String> {
+    prompt();
+    match within {
+        None => Some(next_line().await),
+        Some(within) => match timeout(within, next_line()).await {
+            Ok(line) => Some(line),
+            Err(_) => {
+                println!();
+                None
+            }
+        },
+    }
+}
+
+/// `read_line_within` for the prompts outside of async code.
+/// Needs the multi-threaded runtime, like the one of `main`.
+pub(crate) fn read_line_blocking(within: Option<Duration>) -> Option<String> {
+    // start the default source from the runtime, before leaving it
+    source();
+    block_in_place(|| Handle::current().block_on(read_line_within(within)))
+}
+
+/// Show that we are waiting for input.
+pub(crate) fn prompt() {
+    print!(">>> ");
+    let _ = stdout().flush();
+}
+
+/// The next line of input, without printing the prompt. Exits once the input is over,
+/// e.g. at the end of the script or when stdin is closed.
+/// Cancel safe: used in `tokio::select!`, no line is lost when another branch wins.
+pub(crate) async fn next_line() -> String {
+    match source().next_line().await {
+        Some(line) => line,
+        None => {
+            println!("\nNo more input, goodbye.");
+            std::process::exit(0);
+        }
+    }
+}
+
+static SOURCE: OnceLock<Box<dyn InputSource>> = OnceLock::new();
+
+/// The input source in use, the terminal unless another one was set.
+fn source() -> &'static dyn InputSource {
+    SOURCE.get_or_init(|| Box::new(Terminal::new())).as_ref()
+}
+
+/// Send the lines of stdin to `tx`, until stdin or the receiver is closed.
+async fn forward_stdin(tx: UnboundedSender<String>) {
+    let mut lines = BufReader::new(stdin()).lines();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+            // stdin closed: dropping tx tells the readers
+            Ok(None) => break,
+            Err(e) => {
+                error!("IO Eroor: failed to read line: {e}");
+                break;
+            }
+        }
+    }
+}
+
+/// Stand-in for the driver of the buttons: while a move is expected, the lines typed in the
+/// terminal made only of keys (see `Key::from_char`) are pressed one by one. Other lines,
+/// like the commands, are sent as they are.
+async fn drive_pad_from_stdin(driver: PadDriver) {
+    let (tx, mut lines) = unbounded_channel();
+    tokio::spawn(forward_stdin(tx));
+    while let Some(line) = lines.recv().await {
+        match keys_of(line.trim()) {
+            Some(keys) if driver.expects_move() => {
+                for key in keys {
+                    driver.press(key);
+                }
+            }
+            _ => driver.send_line(line),
+        }
+    }
+}
@@ -2,16 +2,27 @@ extern crate chess;
 extern crate lichess_api;
 extern crate vampirc_uci;
 
+mod analysis;
+mod archive;
+mod buttons;
+mod clock;
+mod config;
+mod input;
+mod notation;
 mod offline;
 mod online;
+mod outbox;
+mod pgn;
 mod stockfish;
 mod utils;
 
+use crate::config::Config;
+use crate::outbox::{LichessStudy, Outbox};
 use crate::utils::*;
 
 use lichess_api::client::LichessApi;
 use lichess_api::error::Result;
-use log::{debug, info};
+use log::info;
 use reqwest::ClientBuilder;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -22,35 +33,99 @@ async fn main() -> Result<()> {
 
     info!("minac v{}", VERSION);
 
-    // main program loop
-    loop {
-        // get the lichess token, panic if you can't read it
+    // read the config file once, panic if it is invalid
+    let config = Config::load().expect("Could not load the minac config file");
+    // moves and commands come from the terminal, a script or the button pad
+    input::set_source(input::from_config(&config.input).expect("Could not set up the input"));
+    notation::set_piece_letters(&config.notation.piece_letters)
+        .expect("Invalid piece letters in the minac config file");
+
+    if config.lichess.token.is_none() {
+        println!("No Lichess token configured: online play and studies won't work.");
+    }
 
-        let token = std::env::var("MINAC_LICHESS_TOKEN")
-            .expect("Could not get MINAC_LICHESS_TOKEN environment variable");
+    // games for the study are uploaded in the background, retrying until they go through
+    let outbox = Outbox::new(&config.archive.dir);
+    if config.lichess.study_id.is_some() {
+        outbox.spawn_uploader(LichessStudy::new(config.lichess.clone()));
+    }
+
+    // a game left when minac stopped can be resumed right away
+    let mut resume = None;
+    if config.lichess.token.is_some() {
+        let ongoing = online::game_setup::ongoing_games(&lichess_api(&config)).await;
+        resume = online::game_setup::ask_to_resume(ongoing);
+    }
 
-        // lichess api and http client creation
-        let client = ClientBuilder::new()
-            .pool_max_idle_per_host(0)
-            .build()
-            .unwrap();
-        let auth_header = String::from(token).trim().to_string();
-        let api = LichessApi::new(client, Some(auth_header));
+    // main program loop
+    loop {
+        let api = lichess_api(&config);
+        if let Some(game) = resume.take() {
+            online::gameplay::online_game(api, &config, Some(game)).await?;
+            continue;
+        }
 
         let mode = get_game_mode();
         if mode == 0 || mode == 1 {
-            let game = match mode {
-                0 => offline::offline_game_2_players(),
-                1 => offline::offline_game_stockfish().await,
+            let record = match mode {
+                0 => offline::offline_game_2_players(&config.time_control),
+                1 => match offline::offline_game_stockfish(
+                    &config.engine,
+                    &config.time_control,
+                    config.player.name.as_deref(),
+                )
+                .await
+                {
+                    Ok(record) => record,
+                    Err(e) => {
+                        println!("Can't play against Stockfish: {e}");
+                        continue;
+                    }
+                },
                 _ => panic!("Math has been broken"),
             };
-            println!("The game is over. Complete PGN: {}", game);
-            online::gameplay::send_pgn_to_study(api, game.to_string()).await?;
-            debug!("Sent the offline game as a chapter in my study");
+            println!("The game is over. Complete PGN:\n{}", record.to_pgn(None));
+
+            // the analysis, if any, ends up in the PGN sent to the study
+            let mut game_analysis = None;
+            if ask_yes_no("Analyse the game with Stockfish?") {
+                match analysis::analyse_game(&config.engine, &record, config.analysis.depth).await {
+                    Ok(a) => {
+                        a.print_summary();
+                        game_analysis = Some(a);
+                    }
+                    Err(e) => println!("Can't analyse the game: {e}"),
+                };
+            }
+            let pgn = record.to_pgn(game_analysis.as_ref());
+
+            // keep a local copy, and queue the game for the study
+            if let Err(e) = archive::save_game(&config.archive.dir, &pgn) {
+                println!("Can't save the game: {e}");
+            }
+            if config.lichess.study_id.is_some() {
+                if let Err(e) = outbox.queue(&pgn) {
+                    println!("Can't queue the game for the study: {e}");
+                }
+            }
         } else if mode == 2 {
-            online::gameplay::online_game(api).await?;
+            online::gameplay::online_game(api, &config, None).await?;
         } else {
             println!("Option not supported.");
         }
     }
 }
+
+/// Lichess api and http client creation.
+fn lichess_api(config: &Config) -> LichessApi<reqwest::Client> {
+    let client = ClientBuilder::new()
+        .pool_max_idle_per_host(0)
+        .build()
+        .unwrap();
+    let auth_header = config
+        .lichess
+        .token
+        .as_ref()
+        .map(|token| token.trim().to_string());
+    LichessApi::new(client, auth_header)
+}
@@ -0,0 +1,702 @@
+use chess::{Board, BoardStatus, ChessMove, Color, File, MoveGen, Piece, Square};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// Write a legal move in SAN (Standard Algebraic Notation), e.g. `Nbd7`, `exd5`, `e8=Q+`, `O-O`.
+pub(crate) fn to_san(board: &Board, chess_move: ChessMove) -> String {
+    let source = chess_move.get_source();
+    let dest = chess_move.get_dest();
+    let piece = board
+        .piece_on(source)
+        .expect("to_san called with a move from an empty square");
+
+    let mut san = String::new();
+    let file_distance = source
+        .get_file()
+        .to_index()
+        .abs_diff(dest.get_file().to_index());
+
+    if piece == Piece::King && file_distance == 2 {
+        if dest.get_file().to_index() > source.get_file().to_index() {
+            san.push_str("O-O");
+        } else {
+            san.push_str("O-O-O");
+        }
+    } else {
+        let is_capture =
+            board.piece_on(dest).is_some() || (piece == Piece::Pawn && file_distance != 0);
+
+        if piece == Piece::Pawn {
+            if is_capture {
+                san.push(file_char(source));
+            }
+        } else {
+            san.push_str(&piece.to_string(Color::White));
+            san.push_str(&disambiguation(board, piece, source, dest));
+        }
+
+        if is_capture {
+            san.push('x');
+        }
+        san.push_str(&dest.to_string());
+
+        if let Some(promotion) = chess_move.get_promotion() {
+            san.push('=');
+            san.push_str(&promotion.to_string(Color::White));
+        }
+    }
+
+    let after = board.make_move_new(chess_move);
+    if after.status() == BoardStatus::Checkmate {
+        san.push('#');
+    } else if after.checkers().popcnt() > 0 {
+        san.push('+');
+    }
+
+    san
+}
+
+/// Write a line of moves in SAN, stopping at the first move that is not legal.
+pub(crate) fn line_to_san(board: &Board, line: &[ChessMove]) -> Vec<String> {
+    let mut board = *board;
+    let mut san = Vec::new();
+    for chess_move in line {
+        if !board.legal(*chess_move) {
+            break;
+        }
+        san.push(to_san(&board, *chess_move));
+        board = board.make_move_new(*chess_move);
+    }
+    san
+}
+
+/// Write a move in UCI (long algebraic) notation, e.g. `e2e4`, `e7e8q`, `e1g1` for castling.
+/// This is what Lichess and the engines expect.
+pub(crate) fn to_uci(chess_move: ChessMove) -> String {
+    let mut uci = format!("{}{}", chess_move.get_source(), chess_move.get_dest());
+    if let Some(promotion) = chess_move.get_promotion() {
+        uci.push_str(&promotion.to_string(Color::Black));
+    }
+    uci
+}
+
+/// Read a move in UCI (long algebraic) notation, e.g. `e2e4`, `e7e8q`, and check it is legal.
+/// Castling written as the king taking its own rook (`e1h1`, as in Chess960) is understood too.
+pub(crate) fn from_uci(board: &Board, uci: &str) -> Result<ChessMove, String> {
+    let (source, rest) = uci
+        .split_at_checked(2)
+        .ok_or(format!("Invalid UCI move: {uci}"))?;
+    let (dest, promotion) = rest
+        .split_at_checked(2)
+        .ok_or(format!("Invalid UCI move: {uci}"))?;
+
+    let source = Square::from_str(source).map_err(|_| format!("Invalid square in {uci}"))?;
+    let mut dest = Square::from_str(dest).map_err(|_| format!("Invalid square in {uci}"))?;
+    let promotion = match promotion {
+        "" => None,
+        "q" => Some(Piece::Queen),
+        "r" => Some(Piece::Rook),
+        "b" => Some(Piece::Bishop),
+        "n" => Some(Piece::Knight),
+        _ => return Err(format!("Invalid promotion in {uci}")),
+    };
+
+    // king takes rook: castling, the king ends up on the g or c file
+    let side = board.side_to_move();
+    if board.piece_on(source) == Some(Piece::King)
+        && board.piece_on(dest) == Some(Piece::Rook)
+        && board.color_on(dest) == Some(side)
+    {
+        let file = if dest.get_file() > source.get_file() {
+            File::G
+        } else {
+            File::C
+        };
+        dest = Square::make_square(source.get_rank(), file);
+    }
+
+    let chess_move = ChessMove::new(source, dest, promotion);
+    if board.legal(chess_move) {
+        Ok(chess_move)
+    } else {
+        Err(format!("Illegal move in this position: {uci}"))
+    }
+}
+
+/// Read a move typed by the player, leniently:
+/// * SAN, UCI, or LAN (`Ng1-f3`, `e7xd8=Q`), with the piece letters of the config
+/// * castling written `O-O`, `0-0`, `oo`...
+/// * any case: `nf3`, `NF3`, `E2E4`
+/// * the shortest input that leaves a single legal move, to save key presses:
+///   the destination (`d5`) or the piece and destination (`Nd5`)
+///
+/// When several moves match, the error lists them. When none does, it suggests the closest ones.
+pub(crate) fn parse_move(board: &Board, input: &str) -> Result<ChessMove, String> {
+    read_move(board, input, piece_letters())
+}
+
+/// `parse_move` with these piece letters.
+fn read_move(board: &Board, input: &str, letters: &[char; 5]) -> Result<ChessMove, String> {
+    for variant in input_variants(input, letters) {
+        let moves = matching_moves(board, &variant);
+        match moves.as_slice() {
+            [] => continue,
+            [chess_move] => return Ok(*chess_move),
+            _ => {
+                return Err(format!(
+                    "{input} is ambiguous, it could be {}",
+                    localized_list(board, &moves)
+                ));
+            }
+        }
+    }
+
+    let suggestions = suggestions(board, input, letters);
+    if suggestions.is_empty() {
+        Err(format!("No legal move matches {input}"))
+    } else {
+        Err(format!(
+            "No legal move matches {input}, did you mean {}?",
+            localized_list(board, &suggestions)
+        ))
+    }
+}
+
+/// Set the letters of the king, queen, rook, bishop and knight, used to read and show moves,
+/// e.g. `RDTFC` in French. Only the first call has an effect.
+pub(crate) fn set_piece_letters(letters: &str) -> Result<(), String> {
+    let letters: Vec<char> = letters.chars().collect();
+    let Ok(letters) = <[char; 5]>::try_from(letters) else {
+        return Err(
+            "Five piece letters are needed: king, queen, rook, bishop and knight".to_string(),
+        );
+    };
+    if letters.iter().any(|c| !c.is_uppercase()) {
+        return Err("The piece letters must be uppercase".to_string());
+    }
+    if letters
+        .iter()
+        .enumerate()
+        .any(|(i, c)| letters[..i].contains(c))
+    {
+        return Err("The piece letters must all be different".to_string());
+    }
+    let _ = PIECE_LETTERS.set(letters);
+    Ok(())
+}
+
+/// A SAN move with the piece letters of the config, to show it to the player.
+/// Saved games keep the English letters.
+pub(crate) fn localize(san: &str) -> String {
+    san.chars()
+        .map(
+            |c| match ENGLISH_LETTERS.iter().position(|&english| english == c) {
+                Some(index) => piece_letters()[index],
+                None => c,
+            },
+        )
+        .collect()
+}
+
+/// Read a line of moves in UCI notation separated by spaces, e.g. the moves of a Lichess game.
+pub(crate) fn line_from_uci(board: &Board, line: &str) -> Result<Vec<ChessMove>, String> {
+    let mut board = *board;
+    let mut moves = Vec::new();
+    for uci in line.split_whitespace() {
+        let chess_move = from_uci(&board, uci)?;
+        moves.push(chess_move);
+        board = board.make_move_new(chess_move);
+    }
+    Ok(moves)
+}
+
+/// The source file, rank or square needed when several pieces of the same type can reach `dest`.
+fn disambiguation(board: &Board, piece: Piece, source: Square, dest: Square) -> String {
+    let others: Vec<Square> = MoveGen::new_legal(board)
+        .filter(|m| m.get_dest() == dest && m.get_source() != source)
+        .map(|m| m.get_source())
+        .filter(|sq| board.piece_on(*sq) == Some(piece))
+        .collect();
+
+    if others.is_empty() {
+        String::new()
+    } else if others.iter().all(|sq| sq.get_file() != source.get_file()) {
+        file_char(source).to_string()
+    } else if others.iter().all(|sq| sq.get_rank() != source.get_rank()) {
+        rank_char(source).to_string()
+    } else {
+        source.to_string()
+    }
+}
+
+fn piece_from_letter(letter: &str) -> Result<Piece, String> {
+    match letter {
+        "K" => Ok(Piece::King),
+        "Q" => Ok(Piece::Queen),
+        "R" => Ok(Piece::Rook),
+        "B" => Ok(Piece::Bishop),
+        "N" => Ok(Piece::Knight),
+        "P" => Ok(Piece::Pawn),
+        _ => Err(format!("Invalid piece: {letter}")),
+    }
+}
+
+// letters of the king, queen, rook, bishop and knight in SAN
+const ENGLISH_LETTERS: [char; 5] = ['K', 'Q', 'R', 'B', 'N'];
+// how far a move can be from the input to be suggested, in typos
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+const MAX_SUGGESTIONS: usize = 3;
+
+static PIECE_LETTERS: OnceLock<[char; 5]> = OnceLock::new();
+
+fn piece_letters() -> &'static [char; 5] {
+    PIECE_LETTERS.get_or_init(|| ENGLISH_LETTERS)
+}
+
+/// The ways an input can be read, most likely first: as typed, with the piece capitalized,
+/// and all lowercase. The piece letters of the config are translated, castling is normalized.
+fn input_variants(input: &str, letters: &[char; 5]) -> Vec<String> {
+    let input = input.trim().trim_end_matches(['+', '#', '!', '?']);
+
+    // castling, with zeros, in lowercase or without the dashes
+    match input
+        .to_uppercase()
+        .replace('0', "O")
+        .replace('-', "")
+        .as_str()
+    {
+        "OO" => return vec![String::from("O-O")],
+        "OOO" => return vec![String::from("O-O-O")],
+        _ => (),
+    };
+
+    let mut capitalized: String = input.chars().take(1).flat_map(char::to_uppercase).collect();
+    capitalized.extend(input.chars().skip(1).flat_map(char::to_lowercase));
+
+    let mut variants = Vec::new();
+    for variant in [input.to_string(), capitalized, input.to_lowercase()] {
+        let variant = translate_pieces(&variant, letters);
+        if !variants.contains(&variant) {
+            variants.push(variant);
+        }
+    }
+    variants
+}
+
+/// Replace the piece letters with the English ones, the promotion included.
+fn translate_pieces(input: &str, letters: &[char; 5]) -> String {
+    let mut translated = String::new();
+    let mut after_equal = false;
+    for c in input.chars() {
+        // the promotion may be typed in lowercase
+        let c = if after_equal {
+            c.to_ascii_uppercase()
+        } else {
+            c
+        };
+        after_equal = c == '=';
+        match letters.iter().position(|&letter| letter == c) {
+            Some(index) => translated.push(ENGLISH_LETTERS[index]),
+            None => translated.push(c),
+        }
+    }
+    translated
+}
+
+/// The legal moves an input can mean, read as SAN, UCI, LAN, or a destination
+/// with the piece in front of it or not.
+fn matching_moves(board: &Board, input: &str) -> Vec<ChessMove> {
+    if let Ok(chess_move) = ChessMove::from_san(board, input) {
+        return vec![chess_move];
+    }
+    if let Some(chess_move) = lan_to_uci(input).and_then(|uci| from_uci(board, &uci).ok()) {
+        return vec![chess_move];
+    }
+
+    let (piece, dest) = match input.len() {
+        2 => (None, input),
+        3 => {
+            let Some((letter, dest)) = input.split_at_checked(1) else {
+                return Vec::new();
+            };
+            let Ok(piece) = piece_from_letter(letter) else {
+                return Vec::new();
+            };
+            (Some(piece), dest)
+        }
+        _ => return Vec::new(),
+    };
+    let Ok(dest) = Square::from_str(dest) else {
+        return Vec::new();
+    };
+
+    MoveGen::new_legal(board)
+        .filter(|m| m.get_dest() == dest)
+        .filter(|m| piece.is_none() || board.piece_on(m.get_source()) == piece)
+        .collect()
+}
+
+/// A move in LAN (`Ng1-f3`, `e7xd8=Q`) or UCI written as UCI, `None` if it isn't one.
+fn lan_to_uci(input: &str) -> Option<String> {
+    let input = input.trim_start_matches(ENGLISH_LETTERS);
+    let uci: String = input
+        .chars()
+        .filter(|c| !matches!(c, '-' | 'x' | '='))
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    matches!(uci.len(), 4 | 5).then_some(uci)
+}
+
+/// The legal moves closest to what was typed, a few typos away at most.
+fn suggestions(board: &Board, input: &str, letters: &[char; 5]) -> Vec<ChessMove> {
+    let Some(input) = input_variants(input, letters).into_iter().next() else {
+        return Vec::new();
+    };
+    let input = input.to_lowercase();
+
+    let mut close: Vec<(usize, ChessMove)> = MoveGen::new_legal(board)
+        .map(|m| {
+            let san = to_san(board, m).trim_end_matches(['+', '#']).to_lowercase();
+            let distance = edit_distance(&input, &san).min(edit_distance(&input, &to_uci(m)));
+            (distance, m)
+        })
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .collect();
+    close.sort_by_key(|(distance, _)| *distance);
+    close
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, m)| m)
+        .collect()
+}
+
+/// Number of characters to insert, remove or replace to go from `a` to `b` (Levenshtein).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(ca != *cb);
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Moves in SAN with the piece letters of the config, separated by commas.
+fn localized_list(board: &Board, moves: &[ChessMove]) -> String {
+    let moves: Vec<String> = moves.iter().map(|m| localize(&to_san(board, *m))).collect();
+    moves.join(", ")
+}
+
+fn file_char(square: Square) -> char {
+    (b'a' + square.get_file().to_index() as u8) as char
+}
+
+fn rank_char(square: Square) -> char {
+    (b'1' + square.get_rank().to_index() as u8) as char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(fen: &str) -> Board {
+        Board::from_str(fen).unwrap()
+    }
+
+    fn square_move(source: Square, dest: Square) -> ChessMove {
+        ChessMove::new(source, dest, None)
+    }
+
+    // a white pawn about to promote
+    const PROMOTION: &str = "8/4P3/8/8/8/k7/8/K7 w - - 0 1";
+    const CASTLING: &str = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+
+    #[test]
+    fn from_uci_reads_the_promotions() {
+        let board = board(PROMOTION);
+        for (uci, piece) in [
+            ("e7e8q", Piece::Queen),
+            ("e7e8r", Piece::Rook),
+            ("e7e8b", Piece::Bishop),
+            ("e7e8n", Piece::Knight),
+        ] {
+            let chess_move = from_uci(&board, uci).unwrap();
+            assert_eq!(chess_move.get_promotion(), Some(piece));
+            assert_eq!(to_uci(chess_move), uci);
+        }
+        assert_eq!(
+            from_uci(&board, "e7e8k"),
+            Err(String::from("Invalid promotion in e7e8k"))
+        );
+        // a pawn on the last rank must promote
+        assert!(from_uci(&board, "e7e8").is_err());
+    }
+
+    #[test]
+    fn from_uci_reads_en_passant() {
+        let board = board("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1");
+        let chess_move = from_uci(&board, "e5d6").unwrap();
+        assert_eq!(chess_move, square_move(Square::E5, Square::D6));
+        assert_eq!(board.make_move_new(chess_move).piece_on(Square::D5), None);
+        assert_eq!(to_san(&board, chess_move), "exd6");
+    }
+
+    #[test]
+    fn from_uci_reads_king_takes_rook_as_castling() {
+        let white = board(CASTLING);
+        for uci in ["e1h1", "e1g1"] {
+            assert_eq!(
+                from_uci(&white, uci),
+                Ok(square_move(Square::E1, Square::G1))
+            );
+        }
+        for uci in ["e1a1", "e1c1"] {
+            assert_eq!(
+                from_uci(&white, uci),
+                Ok(square_move(Square::E1, Square::C1))
+            );
+        }
+        let black = board("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1");
+        assert_eq!(
+            from_uci(&black, "e8h8"),
+            Ok(square_move(Square::E8, Square::G8))
+        );
+        assert_eq!(to_san(&white, square_move(Square::E1, Square::G1)), "O-O");
+        assert_eq!(to_san(&white, square_move(Square::E1, Square::C1)), "O-O-O");
+    }
+
+    #[test]
+    fn from_uci_rejects_what_is_not_a_legal_move() {
+        let board = Board::default();
+        assert_eq!(
+            from_uci(&board, "e2e5"),
+            Err(String::from("Illegal move in this position: e2e5"))
+        );
+        assert!(from_uci(&board, "e2").is_err());
+        assert!(from_uci(&board, "z2e4").is_err());
+        assert!(from_uci(&board, "e2e4x").is_err());
+    }
+
+    #[test]
+    fn line_from_uci_reads_the_moves_of_a_lichess_game() {
+        let start = Board::default();
+        let moves = line_from_uci(&start, "e2e4 e7e5 g1f3 b8c6 f1b5 a7a6 b5c6 d7c6 e1h1").unwrap();
+        assert_eq!(moves.len(), 9);
+        assert_eq!(moves[8], square_move(Square::E1, Square::G1));
+        assert_eq!(
+            line_to_san(&start, &moves).join(" "),
+            "e4 e5 Nf3 Nc6 Bb5 a6 Bxc6 dxc6 O-O"
+        );
+
+        assert_eq!(line_from_uci(&start, ""), Ok(Vec::new()));
+        assert!(line_from_uci(&start, "e2e4 e2e4").is_err());
+    }
+
+    #[test]
+    fn to_san_disambiguates_by_file_rank_or_square() {
+        // knights on b1 and f1
+        let knights = board("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1");
+        assert_eq!(
+            to_san(&knights, square_move(Square::B1, Square::D2)),
+            "Nbd2"
+        );
+        // rooks on a1 and a5
+        let rooks = board("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1");
+        assert_eq!(to_san(&rooks, square_move(Square::A1, Square::A3)), "R1a3");
+        // queens on a1, a3 and c1
+        let queens = board("7K/8/8/7k/8/Q7/8/Q1Q5 w - - 0 1");
+        assert_eq!(
+            to_san(&queens, square_move(Square::A1, Square::B2)),
+            "Qa1b2"
+        );
+        assert_eq!(to_san(&queens, square_move(Square::A3, Square::A2)), "Q3a2");
+    }
+
+    #[test]
+    fn to_san_marks_checks_and_mates() {
+        let start = Board::default();
+        let moves = line_from_uci(&start, "f2f3 e7e5 g2g4 d8h4").unwrap();
+        assert_eq!(line_to_san(&start, &moves).join(" "), "f3 e5 g4 Qh4#");
+
+        let board = board("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        assert_eq!(to_san(&board, square_move(Square::A1, Square::A8)), "Ra8+");
+    }
+
+    #[test]
+    fn destination_alone_is_enough_when_only_one_move_goes_there() {
+        // 1. e4 d5: only the e pawn can go to d5, by taking
+        let board = board("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2");
+        let exd5 = square_move(Square::E4, Square::D5);
+        assert_eq!(matching_moves(&board, "d5"), vec![exd5]);
+        assert_eq!(parse_move(&board, "d5"), Ok(exd5));
+    }
+
+    #[test]
+    fn piece_and_destination_pick_the_piece() {
+        // 1. e4 e5 2. Bc4 Nc6: Bf7 for Bxf7+
+        let start = Board::default();
+        let moves = line_from_uci(&start, "e2e4 e7e5 f1c4 b8c6").unwrap();
+        let board = moves.iter().fold(start, |board, m| board.make_move_new(*m));
+        let bxf7 = square_move(Square::C4, Square::F7);
+        assert_eq!(matching_moves(&board, "Bf7"), vec![bxf7]);
+        assert_eq!(parse_move(&board, "Bf7"), Ok(bxf7));
+        // no knight goes there
+        assert!(matching_moves(&board, "Nf7").is_empty());
+    }
+
+    #[test]
+    fn several_candidates_are_listed() {
+        // a pawn and a knight can take on d5
+        let takes = board("4k3/8/8/3p4/4P3/2N5/8/4K3 w - - 0 1");
+        assert_eq!(matching_moves(&takes, "d5").len(), 2);
+        let error = parse_move(&takes, "d5").unwrap_err();
+        assert!(
+            error.starts_with("d5 is ambiguous, it could be "),
+            "{error}"
+        );
+        assert!(error.contains("exd5") && error.contains("Nxd5"), "{error}");
+        // the piece settles it
+        assert_eq!(
+            parse_move(&takes, "Nd5"),
+            Ok(square_move(Square::C3, Square::D5))
+        );
+
+        // the four promotions
+        let promoting = board(PROMOTION);
+        assert_eq!(matching_moves(&promoting, "e8").len(), 4);
+        let error = parse_move(&promoting, "e8").unwrap_err();
+        for promotion in ["e8=Q", "e8=R", "e8=B", "e8=N"] {
+            assert!(error.contains(promotion), "{error}");
+        }
+        assert_eq!(
+            parse_move(&promoting, "e8=N"),
+            Ok(ChessMove::new(Square::E7, Square::E8, Some(Piece::Knight)))
+        );
+    }
+
+    // Roi, Dame, Tour, Fou, Cavalier
+    const FRENCH: [char; 5] = ['R', 'D', 'T', 'F', 'C'];
+
+    #[test]
+    fn castling_in_any_spelling() {
+        let board = board(CASTLING);
+        for input in ["O-O", "0-0", "oo", "o-o", "OO+"] {
+            assert_eq!(
+                parse_move(&board, input),
+                Ok(square_move(Square::E1, Square::G1)),
+                "{input}"
+            );
+        }
+        for input in ["O-O-O", "0-0-0", "ooo"] {
+            assert_eq!(
+                parse_move(&board, input),
+                Ok(square_move(Square::E1, Square::C1)),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn any_case_and_uci() {
+        let board = Board::default();
+        let nf3 = square_move(Square::G1, Square::F3);
+        for input in ["Nf3", "nf3", "NF3", "g1f3", "G1F3"] {
+            assert_eq!(parse_move(&board, input), Ok(nf3), "{input}");
+        }
+        assert_eq!(
+            parse_move(&board, "E2E4"),
+            Ok(square_move(Square::E2, Square::E4))
+        );
+    }
+
+    #[test]
+    fn long_algebraic_notation() {
+        assert_eq!(
+            parse_move(&Board::default(), "Ng1-f3"),
+            Ok(square_move(Square::G1, Square::F3))
+        );
+        assert_eq!(lan_to_uci("Ng1-f3"), Some(String::from("g1f3")));
+
+        // a rook to take on d8, promoting
+        let capture = board("3rk3/4P3/8/8/8/8/8/4K3 w - - 0 1");
+        let promotion = ChessMove::new(Square::E7, Square::D8, Some(Piece::Queen));
+        assert_eq!(lan_to_uci("e7xd8=Q"), Some(String::from("e7d8q")));
+        assert_eq!(parse_move(&capture, "e7xd8=Q"), Ok(promotion));
+        assert_eq!(parse_move(&capture, "exd8=Q+"), Ok(promotion));
+    }
+
+    #[test]
+    fn french_piece_letters() {
+        let start = Board::default();
+        assert_eq!(
+            read_move(&start, "Cf3", &FRENCH),
+            Ok(square_move(Square::G1, Square::F3))
+        );
+
+        // 1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6: the queen mates on f7
+        let moves = line_from_uci(&start, "e2e4 e7e5 d1h5 b8c6 f1c4 g8f6").unwrap();
+        let scholar = moves.iter().fold(start, |board, m| board.make_move_new(*m));
+        let qxf7 = square_move(Square::H5, Square::F7);
+        assert_eq!(read_move(&scholar, "Dxf7", &FRENCH), Ok(qxf7));
+        assert_eq!(read_move(&scholar, "Dxf7#", &FRENCH), Ok(qxf7));
+        assert_eq!(read_move(&scholar, "dxf7", &FRENCH), Ok(qxf7));
+
+        // the promotion, in lowercase
+        let queen = ChessMove::new(Square::E7, Square::E8, Some(Piece::Queen));
+        assert_eq!(read_move(&board(PROMOTION), "e8=d", &FRENCH), Ok(queen));
+        assert_eq!(read_move(&board(PROMOTION), "e8=D", &FRENCH), Ok(queen));
+        assert_eq!(translate_pieces("Txe8=c", &FRENCH), "Rxe8=N");
+    }
+
+    #[test]
+    fn the_input_as_typed_wins_over_the_capitalized_piece() {
+        // the b pawn and the bishop can both take on c3
+        let english = board("7k/8/8/8/8/2n5/1P6/4B2K w - - 0 1");
+        assert_eq!(
+            parse_move(&english, "bxc3"),
+            Ok(square_move(Square::B2, Square::C3))
+        );
+        assert_eq!(
+            parse_move(&english, "Bxc3"),
+            Ok(square_move(Square::E1, Square::C3))
+        );
+        assert_eq!(
+            input_variants("bxc3", &ENGLISH_LETTERS),
+            vec![String::from("bxc3"), String::from("Bxc3")]
+        );
+
+        // in French, f is a file and F the bishop
+        let french = board("7k/8/8/4n3/5P2/8/1B6/7K w - - 0 1");
+        assert_eq!(
+            read_move(&french, "fxe5", &FRENCH),
+            Ok(square_move(Square::F4, Square::E5))
+        );
+        assert_eq!(
+            read_move(&french, "Fxe5", &FRENCH),
+            Ok(square_move(Square::B2, Square::E5))
+        );
+    }
+
+    #[test]
+    fn suggests_the_closest_moves() {
+        let board = Board::default();
+        let error = parse_move(&board, "Nf4").unwrap_err();
+        assert!(
+            error.starts_with("No legal move matches Nf4, did you mean "),
+            "{error}"
+        );
+        assert!(error.contains("Nf3") && error.ends_with('?'), "{error}");
+        assert!(suggestions(&board, "Nf4", &ENGLISH_LETTERS).len() <= MAX_SUGGESTIONS);
+
+        assert_eq!(
+            parse_move(&board, "xyz123"),
+            Err(String::from("No legal move matches xyz123"))
+        );
+        assert_eq!(edit_distance("nf4", "nf3"), 1);
+        assert_eq!(edit_distance("e2e4", "e4"), 2);
+    }
+}
@@ -1,25 +1,53 @@
+use crate::analysis::winning_chances;
+use crate::clock::{Clock, Flag, TimeControl};
+use crate::config::{EngineConfig, TimeControlConfig};
+use crate::notation::{line_to_san, localize, parse_move, to_uci};
 use crate::online::commands::{StockfishInput, StockfishOutput};
-use crate::stockfish::{launch_stockfish, receive_stockfish_best_move, send_move_to_stockfish};
-use crate::utils::{ask_for_move, ask_for_side};
-use chess::{ChessMove, Game};
+use crate::pgn::GameRecord;
+use crate::stockfish::{EngineSession, SearchInfo, clock_time_control};
+use crate::utils::{
+    ask_for_engine_strength, ask_for_move_within, ask_for_search_limit, ask_for_side,
+    ask_for_time_control,
+};
+use chess::{Board, Color};
 use log::{debug, error};
-use tokio::spawn;
-use tokio::sync::mpsc;
+use std::io;
+use std::io::Write;
 
-const DEPTH: i64 = 5;
-const LEVEL: i64 = 1;
+// width of the evaluation bar shown while the engine thinks
+const EVAL_BAR_WIDTH: usize = 20;
 
-pub(crate) fn offline_game_2_players() -> Game {
-    let mut game = Game::new();
+pub(crate) fn offline_game_2_players(time_control: &TimeControlConfig) -> GameRecord {
+    let mut record = GameRecord::new(Board::default());
     println!("Offline game two players. You input all moves.");
 
+    record.event = String::from("Offline game");
+
+    // white's clock starts right away
+    let default_control = TimeControl::fischer(time_control.minutes, time_control.increment);
+    record.time_control = ask_for_time_control(default_control);
+    let mut clock = record.time_control.clone().map(Clock::new);
+    if let Some(clock) = &mut clock {
+        clock.start(Color::White);
+    }
+
     // while the game is still ongoing
-    while game.result().is_none() {
-        let current_board = game.current_position();
+    while !record.is_over() {
+        let current_board = record.game.current_position();
+        let side_to_move = record.game.side_to_move();
         println!("Current FEN: {}", current_board);
+        if let Some(clock) = &clock {
+            println!("{}", clock);
+        }
 
-        println!("{:?} to move.", game.side_to_move());
-        let (next_move_str, move_option) = ask_for_move();
+        println!("{:?} to move.", side_to_move);
+        let timeout = clock.as_ref().and_then(Clock::time_until_flag);
+        let Some((next_move_str, move_option)) = ask_for_move_within(&current_board, timeout)
+        else {
+            println!("{:?} ran out of time.", side_to_move);
+            record.flag(side_to_move);
+            break;
+        };
         match move_option {
             Some(_) => {
                 println!("Move option specified, ending game.");
@@ -28,9 +56,9 @@ pub(crate) fn offline_game_2_players() -> Game {
             _ => (),
         };
 
-        // convert the SAN to a valid move
+        // convert the input to a valid move
         // will repeat the loop if the move is not valid
-        let next_move = match ChessMove::from_san(&current_board, &next_move_str) {
+        let next_move = match parse_move(&current_board, &next_move_str) {
             Ok(m) => m,
             Err(e) => {
                 println!("Problem with the chess move, try again. Error: {}", e);
@@ -38,59 +66,93 @@ pub(crate) fn offline_game_2_players() -> Game {
             }
         };
 
+        // press the clock: the move only counts if the flag did not fall meanwhile
+        let time_left = match clock.as_mut().map(Clock::press) {
+            Some(Ok(time_left)) => Some(time_left),
+            Some(Err(Flag(color))) => {
+                println!("{:?} ran out of time.", color);
+                record.flag(color);
+                break;
+            }
+            None => None,
+        };
+
         // make the move
-        game.make_move(next_move);
+        record.make_move(next_move, time_left);
     }
 
-    game
+    if let Some(clock) = &mut clock {
+        clock.stop();
+        println!("{}", clock);
+    }
+
+    record
 }
 
-pub(crate) async fn offline_game_stockfish() -> Game {
+pub(crate) async fn offline_game_stockfish(
+    engine: &EngineConfig,
+    time_control: &TimeControlConfig,
+    player_name: Option<&str>,
+) -> io::Result<GameRecord> {
     // play an offline game against stockfish
-    let mut game = Game::new();
+    let mut record = GameRecord::new(Board::default());
     let chosen_side = ask_for_side();
     debug!("Choosing side {:?}", chosen_side);
 
-    // launch stockfish in a seperate thread
-    let mut stockfish_child = launch_stockfish().await;
-
-    // take the stdin and stdout of the stockfish child process
-    let stockfish_in = stockfish_child
-        .stdin
-        .take()
-        .expect("Stockfish child has no stdin handle");
-    let stockfish_out = stockfish_child
-        .stdout
-        .take()
-        .expect("Stockfish child has no stdin handle");
-
-    // channels for stockfish in/out
-    let (tx_in, rx_in) = mpsc::channel(10);
-    let (tx_out, mut rx_out) = mpsc::channel(40);
-
-    // tasks for handling stockfish in/out
-    spawn(send_move_to_stockfish(stockfish_in, rx_in));
-    spawn(receive_stockfish_best_move(stockfish_out, tx_out));
-
-    // start by configuring the level and depth of the engine
-    let stockfish_config = StockfishInput::Configure {
-        level: LEVEL,
-        depth: DEPTH,
+    // launch stockfish, then choose how strong it plays
+    let session = EngineSession::start(engine).await?;
+    let strength = ask_for_engine_strength(engine.strength(), session.elo_range());
+    let limit = ask_for_search_limit(engine.search_limit());
+    let default_control = TimeControl::fischer(time_control.minutes, time_control.increment);
+    record.time_control = ask_for_time_control(default_control);
+    let mut clock = record.time_control.clone().map(Clock::new);
+
+    // players of the PGN
+    let engine_name = format!(
+        "{} ({:?})",
+        session.name.as_deref().unwrap_or("Stockfish"),
+        strength
+    );
+    let player_name = player_name.unwrap_or("?").to_string();
+    record.event = String::from("Game against Stockfish");
+    (record.white, record.black) = match chosen_side {
+        Color::White => (player_name, engine_name),
+        Color::Black => (engine_name, player_name),
     };
-    tx_in.send(stockfish_config).await.unwrap();
+
+    // run its reader/writer tasks in the background
+    let mut engine_handle = session.spawn();
+
+    // start by configuring the strength and search limit of the engine
+    let stockfish_config = StockfishInput::Configure { strength, limit };
+    engine_handle.tx.send(stockfish_config).await.unwrap();
+
+    // white's clock starts right away
+    if let Some(clock) = &mut clock {
+        clock.start(Color::White);
+    }
 
     // while the game is still ongoing
-    while game.result().is_none() {
-        let current_board = game.current_position();
+    while !record.is_over() {
+        let current_board = record.game.current_position();
+        let side_to_move = record.game.side_to_move();
         println!(
             "Current FEN: {}. {:?} to move.",
-            current_board,
-            game.side_to_move()
+            current_board, side_to_move
         );
+        if let Some(clock) = &clock {
+            println!("{}", clock);
+        }
+        let timeout = clock.as_ref().and_then(Clock::time_until_flag);
 
-        if chosen_side == game.side_to_move() {
+        let next_move = if chosen_side == side_to_move {
             // our turn
-            let (next_move_str, move_option) = ask_for_move();
+            let Some((next_move_str, move_option)) = ask_for_move_within(&current_board, timeout)
+            else {
+                println!("You ran out of time.");
+                record.flag(side_to_move);
+                break;
+            };
             match move_option {
                 Some(_) => {
                     println!("Move option specified, ending game.");
@@ -99,46 +161,136 @@ pub(crate) async fn offline_game_stockfish() -> Game {
                 _ => (),
             };
 
-            // convert the SAN to a valid move
+            // convert the input to a valid move
             // will repeat the loop if the move is not valid
-            let next_move = match ChessMove::from_san(&current_board, &next_move_str) {
+            match parse_move(&current_board, &next_move_str) {
                 Ok(m) => m,
                 Err(e) => {
-                    error!("Can't parse move: {e}");
-                    println!("Problem with the chess move, try again.");
+                    println!("Problem with the chess move, try again. Error: {e}");
                     continue;
                 }
+            }
+        } else {
+            // stockfish's turn: send it the game so far and the clocks
+            let go = StockfishInput::Go {
+                start_fen: record.start().to_string(),
+                moves: record.moves(),
+                time_control: clock
+                    .as_ref()
+                    .map(|clock| clock_time_control(clock, side_to_move)),
             };
+            engine_handle.tx.send(go).await.unwrap();
 
-            // make the move
-            game.make_move(next_move);
-
-            // send the move to stockfish
-            let next_move_stockfish = StockfishInput::PlayerMove {
-                chess_move: next_move,
-                fen: game.current_position().to_string(),
+            // wait for a move message from stockfish, at most until its flag falls
+            // and show what it thinks meanwhile
+            let best_move = async {
+                loop {
+                    match engine_handle.rx.recv().await {
+                        Some(StockfishOutput::StockfishInfo { info }) => {
+                            print_live_eval(&current_board, &info);
+                        }
+                        Some(StockfishOutput::StockfishBestMove { chess_move }) => {
+                            println!();
+                            return Some(chess_move);
+                        }
+                        None => return None,
+                    };
+                }
             };
-            tx_in.send(next_move_stockfish).await.unwrap();
-        } else {
-            // stockfish's turn
-            // wait for a move message from stockfish and play it
-            match rx_out.recv().await {
-                Some(StockfishOutput::StockfishBestMove { chess_move }) => {
+            let best_move = match timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, best_move).await {
+                    Ok(best_move) => best_move,
+                    Err(_) => {
+                        println!("Stockfish ran out of time.");
+                        record.flag(side_to_move);
+                        break;
+                    }
+                },
+                None => best_move.await,
+            };
+
+            match best_move {
+                Some(chess_move) => {
                     debug!(
-                        "Received UCI move from stockfish {}{}, making it in our Game copy",
-                        chess_move.get_source(),
-                        chess_move.get_dest()
+                        "Received UCI move from stockfish {}, making it in our Game copy",
+                        to_uci(chess_move)
                     );
-                    game.make_move(chess_move);
+                    chess_move
                 }
                 None => {
                     error!("Can't get stockfish output (tx_out dropped), aborting game.");
                     break;
                 }
-                _ => {}
-            };
-        }
+            }
+        };
+
+        // press the clock: the move only counts if the flag did not fall meanwhile
+        let time_left = match clock.as_mut().map(Clock::press) {
+            Some(Ok(time_left)) => Some(time_left),
+            Some(Err(Flag(color))) => {
+                println!("{:?} ran out of time.", color);
+                record.flag(color);
+                break;
+            }
+            None => None,
+        };
+
+        // make the move
+        record.make_move(next_move, time_left);
     }
 
-    game
+    if let Some(clock) = &mut clock {
+        clock.stop();
+        println!("{}", clock);
+    }
+
+    Ok(record)
+}
+
+/// Show the engine's evaluation and best line while it thinks, on a single refreshed line.
+fn print_live_eval(board: &Board, info: &SearchInfo) {
+    let Some(score) = info.score.filter(|_| info.is_exact()) else {
+        return;
+    };
+    let score = score.for_white(board.side_to_move());
+
+    // eval bar: white's share of the winning chances
+    let white_share = (winning_chances(score.to_centipawns()) + 1.0) / 2.0;
+    let white_width = (white_share * EVAL_BAR_WIDTH as f64).round() as usize;
+    let bar = format!(
+        "{}{}",
+        "#".repeat(white_width),
+        ".".repeat(EVAL_BAR_WIDTH - white_width)
+    );
+
+    let line = localize(&line_to_san(board, &info.pv).join(" "));
+    print!(
+        "\r[{bar}] {score} depth {}: {line}\x1b[K",
+        info.depth.unwrap_or(0)
+    );
+    let _ = std::io::stdout().flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{Script, set_source};
+
+    // the input source is set once for the whole program: the only test reading input
+    #[tokio::test(flavor = "multi_thread")]
+    async fn scripted_two_player_game() {
+        let script = std::env::temp_dir().join(format!("minac-script-{}.txt", std::process::id()));
+        std::fs::write(
+            &script,
+            "# fool's mate, without a clock\n-\nf3\ne5\n\ng4\n# not a move\ne9\nQh4\n",
+        )
+        .unwrap();
+        set_source(Box::new(Script::load(&script).unwrap()));
+        std::fs::remove_file(&script).unwrap();
+
+        let record = offline_game_2_players(&TimeControlConfig::default());
+        assert!(record.time_control.is_none());
+        assert_eq!(record.moves().len(), 4);
+        assert_eq!(record.result(), "0-1");
+    }
 }
@@ -1,3 +1,4 @@
+use lichess_api::model::board;
 use lichess_api::model::board::stream::events::GameEventInfo;
 use lichess_api::model::challenges;
 
@@ -5,10 +6,38 @@ pub(crate) enum GameCommand {
     CreateBotGame {
         bot_game: challenges::ai::PostRequest,
     },
+    ChallengeUser {
+        challenge: challenges::create::PostRequest,
+    },
+    AcceptChallenge {
+        challenge_id: String,
+    },
+    DeclineChallenge {
+        challenge_id: String,
+        reason: challenges::DeclineReason,
+    },
+    ChallengeReceived {
+        challenge: challenges::ChallengeJson,
+    },
+    ChallengeCanceled {
+        challenge: challenges::ChallengeJson,
+    },
+    ChallengeDeclined {
+        challenge: challenges::ChallengeDeclinedJson,
+    },
+    CreateSeek {
+        seek: board::seek::PostRequest,
+    },
+    /// Lichess refused the seek
+    SeekFailed {
+        error: String,
+    },
     GameStart {
         game: GameEventInfo,
     },
     GameOver,
+    /// Lichess can't be reached anymore: back to the menu
+    Disconnected,
 }
 
 pub(crate) enum PlayCommand {
@@ -16,30 +45,67 @@ pub(crate) enum PlayCommand {
         chess_move: String,
         option: Option<MoveOption>,
     },
-    OpponentMove {
-        chess_move: chess::ChessMove,
+    /// the game as Lichess knows it: starting position and all the moves played
+    Sync {
+        start: chess::Board,
+        moves: Vec<chess::ChessMove>,
     },
     Resign,
-    OpponentGone,
+    /// offer or accept a draw (`true`), decline the opponent's offer (`false`)
+    Draw {
+        accept: bool,
+    },
+    /// propose or accept a takeback (`true`), decline the opponent's proposal (`false`)
+    Takeback {
+        accept: bool,
+    },
+    /// post a message in the chat of the game
+    Say {
+        text: String,
+    },
+    /// abort the game, only possible before both sides made their first move
+    Abort,
+    /// win because the opponent left the game for too long
+    ClaimVictory,
+    /// the opponent left (`gone`) or came back; victory can be claimed after `claim_win_in`
+    OpponentGone {
+        gone: bool,
+        claim_win_in: Option<std::time::Duration>,
+    },
+    /// the game is over: how it ended, and who won if anyone did
+    GameOver {
+        winner: Option<lichess_api::model::Color>,
+        status: lichess_api::model::GameStatus,
+    },
+    /// the game stream is lost for good, the game is left as it is
+    Disconnected,
 }
 
 pub(crate) enum MoveOption {
+    /// the move comes with a draw offer
     Draw,
     Resign,
+    /// the options below come without a move
+    AnswerDraw(bool),
+    AnswerTakeback(bool),
+    Say(String),
+    Abort,
+    ClaimVictory,
 }
 
 pub(crate) enum StockfishOutput {
-    StockfishEval { score: i32 },
+    StockfishInfo { info: crate::stockfish::SearchInfo },
     StockfishBestMove { chess_move: chess::ChessMove },
 }
 
 pub(crate) enum StockfishInput {
-    PlayerMove {
-        chess_move: chess::ChessMove,
-        fen: String,
+    Go {
+        start_fen: String,
+        moves: Vec<chess::ChessMove>,
+        time_control: Option<vampirc_uci::UciTimeControl>,
     },
     Configure {
-        level: i64,
-        depth: i64,
+        strength: crate::stockfish::EngineStrength,
+        limit: crate::stockfish::SearchLimit,
     },
 }
@@ -1,4 +1,8 @@
+use crate::clock::TimeControl;
+use crate::config::{Config, TimeControlConfig};
 use crate::online::commands::*;
+use crate::online::gameplay::{MAX_RECONNECTS, reconnect_delay};
+use crate::utils::*;
 
 use futures::stream::StreamExt;
 use log::{debug, error, info};
@@ -8,63 +12,355 @@ use tokio::time::{Duration, sleep};
 
 use lichess_api::client::LichessApi;
 use lichess_api::error::Result;
+use lichess_api::model::account;
+use lichess_api::model::board;
 use lichess_api::model::board::stream::events;
 use lichess_api::model::challenges;
+use lichess_api::model::games;
+use lichess_api::model::{TimeControl as LichessTimeControl, VariantKey};
+
+// how many ongoing games to list at startup
+const MAX_ONGOING_GAMES: u32 = 10;
+
+/// Ask what kind of online game to play, and send the matching command to `online_game`.
+pub(crate) async fn setup_game(
+    api: LichessApi<Client>,
+    tx: mpsc::Sender<GameCommand>,
+    config: Config,
+) {
+    let command = loop {
+        let command = match get_online_mode() {
+            0 => Some(bot_game(&config.time_control)),
+            1 => Some(user_challenge(&config.time_control)),
+            2 => incoming_challenge(&api).await,
+            3 => Some(seek(&config.time_control)),
+            _ => panic!("Math has been broken"),
+        };
+        if let Some(command) = command {
+            break command;
+        }
+    };
 
-pub(crate) async fn setup_bot_game(tx: mpsc::Sender<GameCommand>) {
     // sleep for a sec, to be sure that the event stream is opened before sending the challenge
     sleep(Duration::from_secs(1)).await;
 
-    // create a game against a bot
-    let level = 1;
+    match tx.send(command).await {
+        Ok(_) => debug!("Setup game: message sent successfully to main runtine"),
+        Err(e) => error!("Setup game: can't send message: {e}"),
+    };
+}
+
+/// Our ongoing games on Lichess, e.g. left when minac crashed or lost the connection.
+pub(crate) async fn ongoing_games(api: &LichessApi<Client>) -> Vec<events::GameEventInfo> {
+    let request = games::ongoing::GetRequest::new(MAX_ONGOING_GAMES);
+    match api.get_ongoing_games(request).await {
+        Ok(ongoing) => ongoing.now_playing,
+        Err(e) => {
+            error!("Can't get the ongoing games: {e}");
+            Vec::new()
+        }
+    }
+}
+
+/// The id of our Lichess account, `None` if it can't be known.
+pub(crate) async fn account_id(api: &LichessApi<Client>) -> Option<String> {
+    match api
+        .get_my_profile(account::profile::GetRequest::new())
+        .await
+    {
+        Ok(profile) => Some(profile.id),
+        Err(e) => {
+            error!("Can't get our Lichess account: {e}");
+            None
+        }
+    }
+}
+
+/// List the ongoing games, and ask whether to resume one of them.
+pub(crate) fn ask_to_resume(games: Vec<events::GameEventInfo>) -> Option<events::GameEventInfo> {
+    if games.is_empty() {
+        return None;
+    }
+
+    println!("You have ongoing games on Lichess:");
+    for (i, game) in games.iter().enumerate() {
+        println!(
+            "* [{i}] against {} ({:?}), you play {:?}",
+            game.opponent.username, game.speed, game.color
+        );
+    }
+    if !ask_yes_no("Resume one of them?") {
+        return None;
+    }
+
+    println!("Choose the game to resume:");
+    let index = ask_for_index(games.len());
+    games.into_iter().nth(index)
+}
+
+/// A game against the Lichess AI.
+fn bot_game(time_control: &TimeControlConfig) -> GameCommand {
+    let level = ask_for_ai_level(1);
+    let clock = ask_for_lichess_clock(time_control, true);
     let ai_challenge = challenges::AIChallenge {
         level,
-        base: challenges::ChallengeBase {
-            clock_increment: None,
-            clock_limit: None,
-            days: None,
-            fen: None,
-            variant: lichess_api::model::VariantKey::Standard,
-        },
-        color: lichess_api::model::Color::Random,
+        base: challenge_base(clock),
+        color: ask_for_challenge_color(),
     };
 
-    // do the POST request
-    let lichess_api_request = GameCommand::CreateBotGame {
+    GameCommand::CreateBotGame {
         bot_game: challenges::ai::PostRequest::new(ai_challenge),
+    }
+}
+
+/// A direct challenge to a Lichess user, e.g. a friend.
+fn user_challenge(time_control: &TimeControlConfig) -> GameCommand {
+    let username = ask_for_text("Enter the Lichess username of your opponent.");
+    let clock = ask_for_lichess_clock(time_control, true);
+    let challenge = challenges::create::CreateChallenge {
+        base: challenge_base(clock),
+        rated: ask_yes_no("Rated game?"),
+        color: ask_for_challenge_color(),
+        keep_alive_stream: false,
+        accept_by_token: None,
+        message: None,
+        rules: None,
     };
-    match tx.send(lichess_api_request).await {
-        Ok(_) => debug!("Setup bot game: message sent successfully to main runtine"),
-        Err(e) => error!("Setup bot game: can't send message: {e}"),
+
+    GameCommand::ChallengeUser {
+        challenge: challenges::create::PostRequest::new(&username, challenge),
+    }
+}
+
+/// Pick one of the challenges we received. `None` if there is none to pick.
+async fn incoming_challenge(api: &LichessApi<Client>) -> Option<GameCommand> {
+    let challenges = match api
+        .challenge_list(challenges::list::GetRequest::new())
+        .await
+    {
+        Ok(challenges) => challenges.r#in,
+        Err(e) => {
+            println!("Can't get your challenges: {e}");
+            return None;
+        }
     };
+    if challenges.is_empty() {
+        println!("You have no incoming challenge.");
+        return None;
+    }
+
+    println!("Choose the challenge to accept:");
+    for (i, challenge) in challenges.iter().enumerate() {
+        println!("* [{i}] {}", describe_challenge(challenge));
+    }
+    let challenge = &challenges[ask_for_index(challenges.len())];
+
+    Some(GameCommand::AcceptChallenge {
+        challenge_id: challenge.id.clone(),
+    })
+}
+
+/// Show a challenge we received, and ask whether to accept or decline it.
+pub(crate) async fn answer_challenge(
+    tx: mpsc::Sender<GameCommand>,
+    challenge: challenges::ChallengeJson,
+) {
+    // wait for a bit before grabbing stdin, to let all stdout msg appear
+    sleep(Duration::from_millis(100)).await;
+    println!("New challenge: {}", describe_challenge(&challenge));
+
+    let command = if ask_yes_no("Accept it?") {
+        GameCommand::AcceptChallenge {
+            challenge_id: challenge.id,
+        }
+    } else {
+        GameCommand::DeclineChallenge {
+            challenge_id: challenge.id,
+            reason: ask_for_decline_reason(),
+        }
+    };
+    match tx.send(command).await {
+        Ok(_) => debug!("Answer challenge: message sent successfully to main runtine"),
+        Err(e) => error!("Answer challenge: can't send message: {e}"),
+    };
+}
+
+/// A public seek in the lobby: anyone can accept it.
+fn seek(time_control: &TimeControlConfig) -> GameCommand {
+    // seeks are real time games only, with whole minutes
+    let (limit, increment) = loop {
+        let (limit, increment) = ask_for_lichess_clock(time_control, false)
+            .expect("Should never happen: clock required");
+        if limit % 60 == 0 {
+            break (limit, increment);
+        }
+        println!("A seek needs a whole number of minutes, like 3+2. Try again.");
+    };
+    let seek = board::seek::SeekRequest {
+        rated: ask_yes_no("Rated game?"),
+        time: limit / 60,
+        increment,
+        days: None,
+        variant: VariantKey::Standard,
+        rating_range: None,
+    };
+
+    GameCommand::CreateSeek {
+        seek: board::seek::PostRequest::new(seek),
+    }
+}
+
+/// Keep the seek in the lobby: Lichess removes it when the connection is closed.
+/// Once someone accepts it, the game is announced in the event stream.
+/// A seek refused by Lichess is reported to `online_game`.
+pub(crate) async fn keep_seek_open(
+    api: LichessApi<Client>,
+    tx: mpsc::Sender<GameCommand>,
+    seek: board::seek::PostRequest,
+) {
+    println!("Seeking an opponent...");
+    let mut stream = match api.board_create_a_seek(seek).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            let _ = tx
+                .send(GameCommand::SeekFailed {
+                    error: e.to_string(),
+                })
+                .await;
+            return;
+        }
+    };
+    while let Some(event) = stream.next().await {
+        if let Err(e) = event {
+            error!("Error while seeking: {e}");
+        }
+    }
+    debug!("Goodbye from keep_seek_open");
+}
+
+/// One line about a challenge: the challenger, their rating, the time control and the variant.
+pub(crate) fn describe_challenge(challenge: &challenges::ChallengeJson) -> String {
+    let challenger = match &challenge.challenger {
+        Some(user) => match user.rating {
+            Some(rating) => format!("{} ({rating})", user.name),
+            None => user.name.clone(),
+        },
+        None => String::from("?"),
+    };
+    let time_control = match &challenge.time_control {
+        LichessTimeControl::Clock {
+            limit, increment, ..
+        } => format!("{}+{increment}", f64::from(*limit) / 60.0),
+        LichessTimeControl::Correspondence { days_per_turn } => {
+            format!("{days_per_turn} days per move")
+        }
+        LichessTimeControl::Unlimited => String::from("no clock"),
+    };
+    let rated = if challenge.rated { "rated" } else { "casual" };
+
+    format!(
+        "{challenger}, {time_control}, {}, {rated}",
+        challenge.variant.name
+    )
+}
+
+/// The clock and variant of a challenge, standard chess from the initial position.
+fn challenge_base(clock: Option<(u32, u32)>) -> challenges::ChallengeBase {
+    challenges::ChallengeBase {
+        clock_increment: clock.map(|(_, increment)| increment),
+        clock_limit: clock.map(|(limit, _)| limit),
+        days: None,
+        fen: None,
+        variant: VariantKey::Standard,
+    }
+}
+
+/// Ask for a time control Lichess can play: initial time and increment in seconds,
+/// `None` for no clock (only if `unlimited` is allowed).
+fn ask_for_lichess_clock(default: &TimeControlConfig, unlimited: bool) -> Option<(u32, u32)> {
+    let default = TimeControl::fischer(default.minutes, default.increment);
+
+    // keep asking if the time control is not playable on Lichess
+    loop {
+        match ask_for_time_control(default.clone()) {
+            None if unlimited => return None,
+            None => println!("A clock is needed here, try again."),
+            Some(time_control) => match time_control.lichess_clock() {
+                Some(clock) => return Some(clock),
+                None => println!(
+                    "Lichess only plays a single stage with an increment, like 5+3. Try again."
+                ),
+            },
+        };
+    }
 }
 
 pub(crate) async fn stream_events(
     api: LichessApi<Client>,
     tx: mpsc::Sender<GameCommand>,
 ) -> Result<()> {
-    let stream_request = events::GetRequest::new();
-    let mut stream = api.board_stream_incoming_events(stream_request).await?;
+    let mut attempt = 0;
 
-    while let Some(event) = stream.next().await {
-        match event {
-            Ok(ev) => {
-                match ev.event {
-                    events::EventData::GameStart { game } => {
-                        info!("Game started: {:#?}", game);
-                        let command = GameCommand::GameStart { game: game };
-                        tx.send(command).await.unwrap();
-                    }
-                    events::EventData::GameFinish { game } => {
-                        info!("Game finished: {:#?}", game);
-                        tx.send(GameCommand::GameOver).await.unwrap();
-                        break;
-                    }
-                    _ => debug!("Unhandled event type"),
-                };
+    // Lichess can close the stream at any time: reopen it until the game is finished
+    'reconnect: loop {
+        let stream_request = events::GetRequest::new();
+        let mut stream = match api.board_stream_incoming_events(stream_request).await {
+            Ok(stream) => {
+                attempt = 0;
+                stream
+            }
+            Err(e) => {
+                attempt += 1;
+                if attempt > MAX_RECONNECTS {
+                    error!("Giving up on the event stream: {e}");
+                    let _ = tx.send(GameCommand::Disconnected).await;
+                    break;
+                }
+                error!("Can't open the event stream, attempt {attempt}: {e}");
+                sleep(reconnect_delay(attempt)).await;
+                continue;
             }
-            Err(e) => error!("Error in event loop: {e}"),
         };
+
+        while let Some(event) = stream.next().await {
+            match event {
+                Ok(ev) => {
+                    match ev.event {
+                        events::EventData::GameStart { game } => {
+                            info!("Game started: {:#?}", game);
+                            let command = GameCommand::GameStart { game: game };
+                            tx.send(command).await.unwrap();
+                        }
+                        events::EventData::GameFinish { game } => {
+                            info!("Game finished: {:#?}", game);
+                            tx.send(GameCommand::GameOver).await.unwrap();
+                            break 'reconnect;
+                        }
+                        events::EventData::Challenge { challenge } => {
+                            info!("Challenge received: {:#?}", challenge);
+                            let command = GameCommand::ChallengeReceived { challenge };
+                            tx.send(command).await.unwrap();
+                        }
+                        events::EventData::ChallengeCanceled { challenge } => {
+                            info!("Challenge canceled: {:#?}", challenge);
+                            let command = GameCommand::ChallengeCanceled { challenge };
+                            tx.send(command).await.unwrap();
+                        }
+                        events::EventData::ChallengeDeclined { challenge } => {
+                            info!("Challenge declined: {:#?}", challenge);
+                            let command = GameCommand::ChallengeDeclined { challenge };
+                            tx.send(command).await.unwrap();
+                        }
+                        _ => debug!("Unhandled event type"),
+                    };
+                }
+                Err(e) => error!("Error in event loop: {e}"),
+            };
+        }
+
+        attempt += 1;
+        debug!("Event stream closed, reopening it");
+        sleep(reconnect_delay(attempt)).await;
     }
     debug!("Goodbye from stream_events");
     Ok(())
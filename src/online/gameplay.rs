@@ -1,56 +1,201 @@
+use crate::archive::save_game;
+use crate::clock::{Clock, TimeControl, format_duration};
+use crate::config::Config;
+use crate::input::{next_line, prompt, set_position};
+use crate::notation::{line_from_uci, localize, parse_move, to_san, to_uci};
 use crate::online::commands::*;
 use crate::online::game_setup::*;
-use crate::utils::ask_for_move;
+use crate::pgn::GameRecord;
+use crate::utils::parse_move_input;
 
 use lichess_api::client::LichessApi;
 use lichess_api::error::Result;
-use lichess_api::model::Color;
 use lichess_api::model::board;
+use lichess_api::model::board::chat::ChatRoom;
 use lichess_api::model::board::stream::events;
 use lichess_api::model::board::stream::game;
-use lichess_api::model::studies;
-use lichess_api::model::studies::import_pgn_into_study::ImportPgnBody;
-use lichess_api::model::studies::import_pgn_into_study::StudyImportPgnChapters;
+use lichess_api::model::challenges;
+use lichess_api::model::{Color, GameStatus};
 
-use chess::{Board, ChessMove, Game};
+use chess::{Board, BoardStatus, ChessMove};
 
 use futures::stream::StreamExt;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use reqwest::Client;
 use tokio::spawn;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, watch};
 use tokio::task::JoinHandle;
-use tokio::time::{Duration, sleep};
+use tokio::time::{Duration, interval, sleep, timeout};
 
+use std::collections::HashSet;
+use std::io::{Write, stdout};
 use std::str::FromStr;
-use std::time::SystemTime;
 
-pub(crate) async fn online_game(api: LichessApi<Client>) -> Result<()> {
+// how long the game task has to wrap up once Lichess reports the game is over
+const GAME_OVER_GRACE: Duration = Duration::from_secs(5);
+// waits between two attempts to reopen a dropped stream, doubled every time
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+// attempts in a row before giving up on a stream
+pub(crate) const MAX_RECONNECTS: u32 = 10;
+// refresh rate of the clocks display
+const CLOCK_TICK: Duration = Duration::from_secs(1);
+// remaining times under which we warn the player, from the highest
+const LOW_TIME_WARNINGS: [Duration; 3] = [
+    Duration::from_secs(60),
+    Duration::from_secs(30),
+    Duration::from_secs(10),
+];
+
+/// Play online: a new game, or `resume` one that was already going on.
+pub(crate) async fn online_game(
+    api: LichessApi<Client>,
+    config: &Config,
+    resume: Option<events::GameEventInfo>,
+) -> Result<()> {
     // mpsc channel for tasks to send commands
     let (tx, mut rx) = mpsc::channel(10);
 
-    // create a new game against a bot
-    spawn(setup_bot_game(tx.clone()));
+    match resume {
+        Some(game) => tx.send(GameCommand::GameStart { game }).await.unwrap(),
+        // ask for the kind of game, and create it
+        None => {
+            spawn(setup_game(api.clone(), tx.clone(), config.clone()));
+        }
+    };
 
     // handle received events, send message here when game ready to play
     let stream_events_handle = spawn(stream_events(api.clone(), tx.clone()));
     let mut currently_playing: Option<JoinHandle<Result<()>>> = None;
+    // Lichess announces the ongoing game again every time the event stream is reopened
+    let mut playing_id: Option<String> = None;
+    let mut seeking: Option<JoinHandle<()>> = None;
+    // Lichess also streams the challenges we send, we tell them by their challenger
+    let account = account_id(&api).await;
+    // the challenges already asked about, Lichess streams them again when reconnecting
+    let mut asked_challenges: HashSet<String> = HashSet::new();
 
     while let Some(cmd) = rx.recv().await {
         match cmd {
             GameCommand::CreateBotGame { bot_game } => {
-                api.challenge_ai(bot_game).await?;
+                if let Err(e) = api.challenge_ai(bot_game).await {
+                    // back to the menu
+                    println!("Can't challenge the AI: {e}");
+                    stream_events_handle.abort();
+                    break;
+                }
+            }
+            GameCommand::ChallengeUser { challenge } => {
+                match api.challenge_create(challenge).await {
+                    Ok(_) => println!("Challenge sent, waiting for your opponent to accept it..."),
+                    Err(e) => {
+                        // back to the menu
+                        println!("Can't send the challenge: {e}");
+                        stream_events_handle.abort();
+                        break;
+                    }
+                }
+            }
+            GameCommand::AcceptChallenge { challenge_id } => {
+                let request = challenges::accept::PostRequest::new(&challenge_id);
+                if let Err(e) = api.challenge_accept(request).await {
+                    // back to the menu
+                    println!("Can't accept the challenge: {e}");
+                    stream_events_handle.abort();
+                    break;
+                }
+            }
+            GameCommand::DeclineChallenge {
+                challenge_id,
+                reason,
+            } => {
+                let request = challenges::decline::PostRequest::new(&challenge_id, Some(reason));
+                if let Err(e) = api.challenge_decline(request).await {
+                    error!("Can't decline challenge {challenge_id}: {e}");
+                }
+            }
+            GameCommand::ChallengeReceived { challenge } => {
+                let ours = challenge
+                    .challenger
+                    .as_ref()
+                    .is_some_and(|user| Some(&user.id) == account.as_ref());
+                if ours {
+                    debug!("Ignoring the challenge we sent: {}", challenge.id);
+                } else if currently_playing.is_some() {
+                    // no prompt in the middle of a game
+                    info!("Declining challenge {} while playing", challenge.id);
+                    let request = challenges::decline::PostRequest::new(
+                        &challenge.id,
+                        Some(challenges::DeclineReason::Later),
+                    );
+                    if let Err(e) = api.challenge_decline(request).await {
+                        error!("Can't decline challenge {}: {e}", challenge.id);
+                    }
+                } else if !asked_challenges.insert(challenge.id.clone()) {
+                    debug!("Challenge {} already asked about", challenge.id);
+                } else {
+                    spawn(answer_challenge(tx.clone(), challenge));
+                }
+            }
+            GameCommand::ChallengeCanceled { challenge } => {
+                println!("Challenge canceled: {}", describe_challenge(&challenge));
+            }
+            GameCommand::ChallengeDeclined { challenge } => {
+                let opponent = challenge
+                    .challenge
+                    .dest_user
+                    .as_ref()
+                    .map_or(String::from("Your opponent"), |user| user.name.clone());
+                println!(
+                    "{opponent} declined your challenge: {}",
+                    challenge.decline_reason
+                );
+                // pick another game
+                spawn(setup_game(api.clone(), tx.clone(), config.clone()));
+            }
+            GameCommand::CreateSeek { seek } => {
+                seeking = Some(spawn(keep_seek_open(api.clone(), tx.clone(), seek)));
+            }
+            GameCommand::SeekFailed { error } => {
+                // back to the menu
+                println!("Can't seek an opponent: {error}");
+                seeking = None;
+                stream_events_handle.abort();
+                break;
             }
             GameCommand::GameStart { game } => {
+                let still_playing = currently_playing
+                    .as_ref()
+                    .is_some_and(|handle| !handle.is_finished());
+                if still_playing && playing_id.as_ref() == Some(&game.game_id) {
+                    debug!("Game {} already being played", game.game_id);
+                    continue;
+                }
+                playing_id = Some(game.game_id.clone());
+                // the seek found an opponent
+                if let Some(handle) = seeking.take() {
+                    handle.abort();
+                }
                 if currently_playing.is_some() {
                     currently_playing.unwrap().abort();
                 }
-                currently_playing = Some(spawn(play(api.clone(), game)));
+                currently_playing =
+                    Some(spawn(play(api.clone(), game, config.clone(), tx.clone())));
             }
-            GameCommand::GameOver => {
-                println!("<<< Game over! >>>\n");
-                if currently_playing.is_some() {
-                    currently_playing.unwrap().abort();
+            GameCommand::GameOver | GameCommand::Disconnected => {
+                match cmd {
+                    GameCommand::GameOver => println!("<<< Game over! >>>\n"),
+                    _ => println!("Lost the connection to Lichess, back to the menu.\n"),
+                };
+                // give the game task a chance to save the game before stopping it
+                if let Some(handle) = currently_playing.take() {
+                    let abort_handle = handle.abort_handle();
+                    if timeout(GAME_OVER_GRACE, handle).await.is_err() {
+                        abort_handle.abort();
+                    }
+                }
+                if let Some(handle) = seeking.take() {
+                    handle.abort();
                 }
                 stream_events_handle.abort();
                 break;
@@ -61,238 +206,465 @@ pub(crate) async fn online_game(api: LichessApi<Client>) -> Result<()> {
     Ok(())
 }
 
-pub(crate) async fn send_pgn_to_study(
-    api: LichessApi<Client>,
-    pgn: String,
-) -> Result<StudyImportPgnChapters> {
-    // TODO: study id + the token should be in a config file for the project instead of files/hardcoded
-    let epoch = std::time::SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
-        .as_secs();
-    let request = studies::import_pgn_into_study::PostRequest::new(
-        "Gz5dfSNQ".to_string(),
-        ImportPgnBody {
-            name: format!("Minac DEV {}", epoch),
-            pgn: pgn,
-            variant: None,
-            orientation: None,
-        },
-    );
-    api.import_pgn_into_study(request).await
-}
-
 pub(crate) async fn stream_current_game(
     api: LichessApi<Client>,
     tx: mpsc::Sender<PlayCommand>,
+    clock_tx: watch::Sender<Option<Clock>>,
     lichess_game: events::GameEventInfo,
 ) -> Result<()> {
-    // stream the state of the board
-    let request = board::stream::game::GetRequest::new(&lichess_game.game_id);
-    let mut stream = api.board_stream_board_state(request).await?;
-
-    // handle the game states
-    while let Some(event) = stream.next().await {
-        match event {
-            Ok(ev) => {
-                match ev {
-                    game::Event::GameFull { game_full } => {
-                        // first event received when opening the stream
-                        // if we are black, only sent after white's first move
-                        debug!("First event GameFull:\n{:#?}", game_full);
-                        if &lichess_game.color == &Color::Black {
-                            println!("You are black. Waiting for opponent's move...");
-                            // only handle the first game state if we are black
-                            handle_current_game_state(tx.clone(), game_full.state).await;
-                        } else {
-                            println!("You are white.");
-                        }
-                    }
-                    game::Event::GameState { game_state } => {
-                        // is it my turn?
-                        let my_color = &lichess_game.color;
-                        // first move is white, so n mod 2 gives 1 when it's black's turn, 0 for white's turn
-                        let current_color = match game_state.moves.split_whitespace().count() % 2 {
-                            1 => &Color::Black,
-                            0 => &Color::White,
-                            _ => {
-                                panic!("Math has been broken.");
-                            }
-                        };
-
-                        // we only handle the game state if it is our turn
-                        if current_color == my_color {
-                            debug!("Handling game state {:#?}: my turn", game_state);
-                            handle_current_game_state(tx.clone(), Some(game_state)).await;
-                        } else {
-                            debug!("Ignoring game state {:#?}: not my turn", game_state);
-                        }
-                    }
-                    game::Event::OpponentGone { opponent_gone: _ } => {
-                        // send msg to play f'n to close all current tasks, and break out of the loop
-                        tx.send(PlayCommand::OpponentGone).await.unwrap();
-                        break;
-                    }
-                    _ => error!("Unhandled event type"),
-                };
+    let mut winner = None;
+    let mut status = GameStatus::Created;
+    let my_color = match lichess_game.color {
+        Color::Black => chess::Color::Black,
+        _ => chess::Color::White,
+    };
+    // the starting position, known from the GameFull event
+    let mut start = Board::default();
+    let mut over = false;
+    let mut attempt = 0;
+    // whether the opponent currently offers a draw or a takeback
+    let mut draw_offered = false;
+    let mut takeback_proposed = false;
+    // the clocks as Lichess sees them, `None` for games without a clock
+    let mut clock: Option<Clock> = None;
+
+    // the stream is closed by Lichess once the game is over: until then, reopen it when it drops
+    while !over {
+        // stream the state of the board
+        let request = board::stream::game::GetRequest::new(&lichess_game.game_id);
+        let mut stream = match api.board_stream_board_state(request).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                attempt += 1;
+                if attempt > MAX_RECONNECTS {
+                    error!("Giving up on the game stream: {e}");
+                    break;
+                }
+                error!("Can't open the game stream, attempt {attempt}: {e}");
+                sleep(reconnect_delay(attempt)).await;
+                continue;
             }
-            Err(e) => error!("Error in event loop of current game: {e}"),
         };
+
+        // handle the game states
+        while let Some(event) = stream.next().await {
+            let game_state = match event {
+                Ok(game::Event::GameFull { game_full }) => {
+                    // first event received when opening the stream, also after a reconnection
+                    debug!("First event GameFull:\n{:#?}", game_full);
+                    attempt = 0;
+                    start = match game_full.initial_fen.as_str() {
+                        "startpos" => Board::default(),
+                        fen => Board::from_str(fen)
+                            .expect("The received starting position from Lichess is invalid"),
+                    };
+                    clock = game_full.clock.as_ref().map(|lichess_clock| {
+                        Clock::new(TimeControl::from_lichess_clock(
+                            Duration::from_millis(lichess_clock.initial.into()),
+                            Duration::from_millis(lichess_clock.increment.into()),
+                        ))
+                    });
+                    println!("You are {:?}.", my_color);
+                    game_full.state
+                }
+                Ok(game::Event::GameState { game_state }) => Some(game_state),
+                Ok(game::Event::OpponentGone { opponent_gone }) => {
+                    // the game goes on: the opponent may come back, or we claim victory
+                    let command = PlayCommand::OpponentGone {
+                        gone: opponent_gone.gone,
+                        claim_win_in: opponent_gone
+                            .claim_win_in_seconds
+                            .map(|seconds| Duration::from_secs(seconds.into())),
+                    };
+                    if tx.send(command).await.is_err() {
+                        debug!("The game task is gone, closing the game stream");
+                        return Ok(());
+                    }
+                    None
+                }
+                Ok(game::Event::ChatLine { chat_line }) => {
+                    println!(
+                        "[{:?} chat] {}: {}",
+                        chat_line.room, chat_line.username, chat_line.text
+                    );
+                    None
+                }
+                Ok(_) => {
+                    error!("Unhandled event type");
+                    None
+                }
+                Err(e) => {
+                    error!("Error in event loop of current game: {e}");
+                    None
+                }
+            };
+            let Some(game_state) = game_state else {
+                continue;
+            };
+
+            if game_state.winner.is_some() {
+                winner = game_state.winner.clone();
+            }
+            status = game_state.status.clone();
+            over = !matches!(status, GameStatus::Created | GameStatus::Started);
+
+            // rebuild the whole game from the server's moves: missed events don't matter
+            let moves = match line_from_uci(&start, &game_state.moves) {
+                Ok(moves) => moves,
+                Err(e) => {
+                    error!(
+                        "Can't read the moves of the game ({e}): {}",
+                        game_state.moves
+                    );
+                    continue;
+                }
+            };
+            let mut board = start;
+            for chess_move in &moves {
+                board = board.make_move_new(*chess_move);
+            }
+            let move_count = moves.len();
+            debug!("Game state {:#?}", game_state);
+            if tx.send(PlayCommand::Sync { start, moves }).await.is_err() {
+                // e.g. we resigned: the final state has nobody to go to
+                debug!("The game task is gone, closing the game stream");
+                return Ok(());
+            }
+
+            // offers of the opponent, only shown when they are made
+            let (opponent_draw, opponent_takeback) = match my_color {
+                chess::Color::White => (game_state.bdraw, game_state.btakeback),
+                chess::Color::Black => (game_state.wdraw, game_state.wtakeback),
+            };
+            if opponent_draw && !draw_offered {
+                println!("Your opponent offers a draw: enter DRAW to accept, NODRAW to decline.");
+            }
+            if opponent_takeback && !takeback_proposed {
+                println!(
+                    "Your opponent proposes a takeback: enter TAKEBACK to accept, NOTAKEBACK to decline."
+                );
+            }
+            (draw_offered, takeback_proposed) = (opponent_draw, opponent_takeback);
+
+            // the clocks only run once both sides made their first move
+            if let Some(clock) = clock.as_mut() {
+                let running = (!over && move_count >= 2).then(|| board.side_to_move());
+                clock.sync(
+                    Duration::from_millis(game_state.wtime.into()),
+                    Duration::from_millis(game_state.btime.into()),
+                    running,
+                );
+                clock_tx.send_replace(Some(clock.clone()));
+            }
+        }
+
+        if !over {
+            attempt += 1;
+            println!("Connection to the game lost, reconnecting...");
+            sleep(reconnect_delay(attempt)).await;
+        }
     }
+
+    // not over: we gave up reconnecting
+    let command = match over {
+        true => PlayCommand::GameOver { winner, status },
+        false => PlayCommand::Disconnected,
+    };
+    let _ = tx.send(command).await;
     debug!("Goodbye from stream_current_game");
     Ok(())
 }
 
+/// How long to wait before reopening a stream, doubling with every failed attempt.
+pub(crate) fn reconnect_delay(attempt: u32) -> Duration {
+    (RECONNECT_DELAY * 2u32.saturating_pow(attempt.saturating_sub(1))).min(MAX_RECONNECT_DELAY)
+}
+
 pub(crate) async fn play(
     api: LichessApi<Client>,
     lichess_game: events::GameEventInfo,
+    config: Config,
+    game_tx: mpsc::Sender<GameCommand>,
 ) -> Result<()> {
     debug!("Playing the game: {:#?}", &lichess_game);
 
     println!("To offer a draw, enter your next move and DRAW at the end. Example: Qxf5DRAW");
+    println!("To offer or accept a draw without moving, enter DRAW. To decline it, NODRAW");
+    println!("To propose or accept a takeback, enter TAKEBACK. To decline it, NOTAKEBACK");
+    println!("To abort the game before the first moves, enter ABORT");
+    println!("To chat with your opponent, enter /say followed by the message. Example: /say gg");
     println!("To resign, enter RESIGN\n");
 
     // record the game with the chess crate
-    let starting_pos = Board::from_str(&lichess_game.fen)
-        .expect("The received starting position from Lichess is invalid");
-    let mut game = Game::new_with_board(starting_pos);
+    // the event only has the current position: the stream syncs the real start and moves
+    let mut record = GameRecord::new(Board::default());
+    let player_name = config.player.name.clone().unwrap_or(String::from("?"));
+    let opponent_name = lichess_game.opponent.username.clone();
+    record.event = String::from("Lichess game");
+    record.site = format!("https://lichess.org/{}", lichess_game.game_id);
+    (record.white, record.black) = match lichess_game.color {
+        Color::Black => (opponent_name, player_name),
+        _ => (player_name, opponent_name),
+    };
+    let my_color = match lichess_game.color {
+        Color::Black => chess::Color::Black,
+        _ => chess::Color::White,
+    };
 
     // channel to receive play commands from board state
     let (tx, mut rx) = mpsc::channel(10);
     // handle current game stream. Connection will be closed when game is over
+    let (clock_tx, clock_rx) = watch::channel(None);
     spawn(stream_current_game(
         api.clone(),
         tx.clone(),
+        clock_tx,
         lichess_game.clone(),
     ));
+    let clock_display = spawn(show_clocks(clock_rx, my_color));
+
+    // our input and the events of the game come at the same time: typing never holds the game up
+    let mut our_turn = false;
+    // aborted games are not worth keeping
+    let mut aborted = false;
+    // the moves Lichess sent last
+    let mut last_sync: Option<Vec<ChessMove>> = None;
+    loop {
+        let cmd = tokio::select! {
+            cmd = rx.recv() => match cmd {
+                Some(cmd) => cmd,
+                None => break,
+            },
+            line = next_line() => input_command(&line),
+        };
 
-    // if we are white, we first need to input a move before any GameState is received by stream_current_game
-    match &lichess_game.color {
-        Color::White => {
-            debug!("We are white. Prompt asking for a move.");
-            handle_current_game_state(tx.clone(), None).await;
-        }
-        _ => (),
-    };
-
-    while let Some(cmd) = rx.recv().await {
         match cmd {
+            PlayCommand::MakeMove { .. } if !our_turn => {
+                println!("Not your turn, wait for your opponent's move.");
+            }
             PlayCommand::MakeMove { chess_move, option } => {
-                let current_position = game.current_position();
-                let game_chess_move = ChessMove::from_san(&current_position, chess_move.as_str());
+                let current_position = record.game.current_position();
+                let game_chess_move = parse_move(&current_position, chess_move.as_str());
 
-                let mut draw = false;
-                match option {
-                    Some(MoveOption::Resign) => {
-                        panic!("Should never happen: no make move should be issued if you resign.")
-                    }
-                    Some(MoveOption::Draw) => draw = true,
-                    _ => (),
-                };
+                let draw = matches!(option, Some(MoveOption::Draw));
 
                 // if the move is valid
-                if game_chess_move.is_ok() {
-                    let valid_move = game_chess_move.unwrap();
-
+                if let Ok(valid_move) = game_chess_move {
                     // make it in our copy
-                    game.make_move(valid_move.clone());
+                    let before = record.moves();
+                    record.make_move(valid_move, None);
 
-                    let uci_move = format!("{}{}", valid_move.get_source(), valid_move.get_dest());
+                    let uci_move = to_uci(valid_move);
 
                     info!("Sending following move to Lichess: {uci_move}");
 
                     // send it to lichess in UCI format
                     let request =
                         board::r#move::PostRequest::new(&lichess_game.game_id, &uci_move, draw);
-                    api.board_make_move(request).await?;
-                    info!("Game progression: {}", game);
-                } else {
-                    println!("The move you entered is not valid. Try again.");
-                    handle_current_game_state(tx.clone(), None).await;
+                    if let Err(e) = api.board_make_move(request).await {
+                        println!("Can't send the move: {e}");
+                        record.resync(record.start(), &before);
+                        continue;
+                    }
+                    info!("Game progression: {}", record.game);
+                } else if let Err(e) = game_chess_move {
+                    println!("The move you entered is not valid ({e}). Try again.");
                 }
             }
-            PlayCommand::OpponentMove { chess_move } => {
-                // only update our game copy
-                // move supplied by the API: should be valid, we don't check
-                println!("Opponent played: {}", chess_move);
-                game.make_move(chess_move);
+            PlayCommand::Sync { start, moves } => {
+                // update our game copy, checking it still matches the one of Lichess
+                let known = record.moves();
+                if record.start() == start && moves.starts_with(&known) {
+                    for chess_move in &moves[known.len()..] {
+                        let position = record.game.current_position();
+                        if position.side_to_move() != my_color {
+                            println!(
+                                "Opponent played: {}",
+                                localize(&to_san(&position, *chess_move))
+                            );
+                        }
+                        record.make_move(*chess_move, None);
+                    }
+                } else if record.start() == start && known.starts_with(&moves) {
+                    // our copy may be a move ahead, sent but not known to Lichess yet:
+                    // only a game shorter than the last one Lichess sent is a takeback
+                    let last_len = last_sync.as_ref().map_or(0, Vec::len);
+                    if moves.len() < last_len {
+                        println!("Takeback: {} moves taken back.", last_len - moves.len());
+                        record.resync(start, &moves);
+                    } else {
+                        debug!("Our move is not known to Lichess yet");
+                    }
+                } else {
+                    warn!("Our copy of the game diverged from Lichess, resynchronizing");
+                    record.resync(start, &moves);
+                }
+                last_sync = Some(moves);
             }
             PlayCommand::Resign => {
                 println!("Resigning.");
                 let request = board::resign::PostRequest::new(&lichess_game.game_id);
-                api.board_resign_game(request).await?;
+                if let Err(e) = api.board_resign_game(request).await {
+                    println!("Can't resign: {e}");
+                    continue;
+                }
+                record.set_result(match lichess_game.color {
+                    Color::Black => "1-0",
+                    _ => "0-1",
+                });
+                break;
+            }
+            PlayCommand::Draw { accept } => {
+                let request = board::draw::PostRequest::new(&lichess_game.game_id, accept);
+                if let Err(e) = api.board_handle_draw(request).await {
+                    println!("Can't send the draw answer: {e}");
+                    continue;
+                }
+            }
+            PlayCommand::Takeback { accept } => {
+                let request = board::takeback::PostRequest::new(&lichess_game.game_id, accept);
+                if let Err(e) = api.board_handle_takeback(request).await {
+                    println!("Can't send the takeback answer: {e}");
+                    continue;
+                }
+            }
+            PlayCommand::Say { text } => {
+                let request =
+                    board::chat::PostRequest::new(&lichess_game.game_id, ChatRoom::Player, &text);
+                if let Err(e) = api.board_write_in_chat(request).await {
+                    println!("Can't send the message: {e}");
+                }
+            }
+            PlayCommand::Abort => {
+                println!("Aborting.");
+                let request = board::abort::PostRequest::new(&lichess_game.game_id);
+                if let Err(e) = api.board_abort_game(request).await {
+                    println!("Can't abort the game: {e}");
+                    continue;
+                }
+            }
+            PlayCommand::ClaimVictory => {
+                let request = board::claim_victory::PostRequest::new(&lichess_game.game_id);
+                if let Err(e) = api.board_claim_victory(request).await {
+                    println!("Can't claim victory: {e}");
+                    continue;
+                }
+            }
+            PlayCommand::OpponentGone { gone, claim_win_in } => match (gone, claim_win_in) {
+                (false, _) => println!("Your opponent is back."),
+                (true, Some(delay)) if !delay.is_zero() => println!(
+                    "Your opponent left the game. You can claim victory in {} seconds.",
+                    delay.as_secs()
+                ),
+                (true, _) => println!("Your opponent left the game. Enter CLAIM to claim victory."),
+            },
+            PlayCommand::GameOver { winner, status } => {
+                match (winner, status) {
+                    (Some(Color::White), _) => record.set_result("1-0"),
+                    (Some(Color::Black), _) => record.set_result("0-1"),
+                    (_, GameStatus::Aborted) => aborted = true,
+                    (None, _) => record.set_result("1/2-1/2"),
+                    _ => (),
+                };
                 break;
             }
-            PlayCommand::OpponentGone => {
-                println!("Opponent gone.");
+            PlayCommand::Disconnected => {
+                println!("Lost the connection to the game.");
+                // no game over will come from the event stream either
+                let _ = game_tx.send(GameCommand::Disconnected).await;
                 break;
             }
         }
+
+        // refresh the prompt: the game changed, or what we typed was handled
+        let position = record.game.current_position();
+        let was_our_turn = our_turn;
+        our_turn = position.side_to_move() == my_color && position.status() == BoardStatus::Ongoing;
+        set_position(our_turn.then_some(position));
+        if our_turn {
+            if !was_our_turn {
+                println!(
+                    "Your turn. Enter the move. Example: Nf3, or just f3 if only one piece can go there"
+                );
+            }
+            prompt();
+        }
     }
 
+    set_position(None);
+    clock_display.abort();
     info!("Stream of the played match closed.");
+
+    // keep a local copy of the game
+    if aborted {
+        info!("Game aborted, not saving it");
+    } else if let Err(e) = save_game(&config.archive.dir, &record.to_pgn(None)) {
+        error!("Can't save the game: {e}");
+    }
     Ok(())
 }
 
-pub(crate) async fn handle_current_game_state(
-    tx: mpsc::Sender<PlayCommand>,
-    game_state: Option<game::GameStateEvent>,
-) {
-    match game_state {
-        None => {
-            info!("No last move supplied: either first move, or wrong move input.");
-            // wait for a bit before grabbing stdin, to let all stdout msg appear
-            sleep(Duration::from_millis(100)).await;
-            let (chess_move, option) = ask_for_move();
-            tx.send(PlayCommand::MakeMove { chess_move, option })
-                .await
-                .unwrap();
-        }
-        Some(game_state) => {
-            // we only receive game states when it is our turn (i.e. event made by opponent)
-
-            // if a winner exists, stop here
-            if game_state.winner.is_some() {
-                return;
+/// Tick the clocks between two game states: on a single line while the opponent thinks,
+/// once when our turn starts, and with a warning every time our time gets low.
+async fn show_clocks(mut clock_rx: watch::Receiver<Option<Clock>>, my_color: chess::Color) {
+    let mut ticker = interval(CLOCK_TICK);
+    let mut last_running = None;
+    // the lowest threshold we warned about, not to warn twice
+    let mut warned: Option<Duration> = None;
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => (),
+            changed = clock_rx.changed() => {
+                // the game stream is gone
+                if changed.is_err() {
+                    break;
+                }
             }
+        };
+        let Some(clock) = clock_rx.borrow().clone() else {
+            continue;
+        };
 
-            // extract the last move from the game state and send it to play f'n
-            // UCI format (ie. source square + dest square, eg. "e2e4")
-            if let Some(last_move) = game_state.moves.split_whitespace().last() {
-                let (source_str_sq, dest_str_sq) = last_move.split_at(2);
-                let source_sq = chess::Square::from_str(source_str_sq).unwrap();
-                let dest_sq = chess::Square::from_str(dest_str_sq).unwrap();
-
-                info!(
-                    "Last move played (UCI notation): {} to {}",
-                    source_str_sq, dest_str_sq
-                );
-
-                let opponent_move = PlayCommand::OpponentMove {
-                    chess_move: ChessMove::new(source_sq, dest_sq, None),
-                };
-                match tx.send(opponent_move).await {
-                    Ok(_) => (),
-                    Err(e) => panic!("Error while sending opponent move: {e}"),
-                };
-
-                // now that we have the opponent's move, prompt for ours
-                // wait for a bit before grabbing stdin, to let all stdout msg appear
-                sleep(Duration::from_millis(100)).await;
-                let (chess_move, option) = ask_for_move();
-                match option {
-                    Some(MoveOption::Resign) => tx.send(PlayCommand::Resign).await.unwrap(),
-                    _ => {
-                        let make_move = PlayCommand::MakeMove { chess_move, option };
-                        tx.send(make_move).await.unwrap();
-                    }
-                };
-            } else {
-                panic!(
-                    "Should never happen: can't extract move from GameState.\nMoves: {}",
-                    game_state.moves
-                );
+        let running = clock.running();
+        if running == Some(my_color) {
+            if last_running != running {
+                println!("\n{clock}");
+            }
+            let remaining = clock.remaining(my_color);
+            // time added by the increment: warn again next time it gets low
+            if warned.is_some_and(|threshold| remaining > threshold) {
+                warned = None;
             }
+            let threshold = LOW_TIME_WARNINGS
+                .iter()
+                .copied()
+                .filter(|threshold| {
+                    remaining <= *threshold && warned.is_none_or(|warned| *threshold < warned)
+                })
+                .last();
+            if let Some(threshold) = threshold {
+                println!("\nLow time: {} left!", format_duration(remaining));
+                warned = Some(threshold);
+            }
+        } else if running.is_some() {
+            print!("\r{clock}   ");
+            let _ = stdout().flush();
         }
+        last_running = running;
+    }
+}
+
+/// The command matching a line typed during the game: a move, the resignation, a draw offer...
+fn input_command(line: &str) -> PlayCommand {
+    let (chess_move, option) = parse_move_input(line);
+    match option {
+        Some(MoveOption::Resign) => PlayCommand::Resign,
+        Some(MoveOption::AnswerDraw(accept)) => PlayCommand::Draw { accept },
+        Some(MoveOption::AnswerTakeback(accept)) => PlayCommand::Takeback { accept },
+        Some(MoveOption::Say(text)) => PlayCommand::Say { text },
+        Some(MoveOption::Abort) => PlayCommand::Abort,
+        Some(MoveOption::ClaimVictory) => PlayCommand::ClaimVictory,
+        _ => PlayCommand::MakeMove { chess_move, option },
     }
 }
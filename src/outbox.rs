@@ -0,0 +1,490 @@
+use crate::config::LichessConfig;
+
+use log::{debug, error, info};
+use reqwest::Client;
+use serde::Deserialize;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::spawn;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, timeout};
+
+// where the studies are, a local server in tests
+const LICHESS_HOST: &str = "https://lichess.org";
+// sub-directory of the archive directory with the games waiting to be uploaded
+const OUTBOX_DIR: &str = "outbox";
+// one line per uploaded game: its hash and the ids of the created chapters
+const UPLOADED_LOG: &str = "uploaded.log";
+// wait between two upload attempts after a failure, doubled every time
+const MIN_BACKOFF: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(600);
+// check the outbox regularly, even when nobody queued anything
+const IDLE_CHECK: Duration = Duration::from_secs(300);
+
+/// Where the games end up. Implemented by Lichess studies, and by stand-ins in tests.
+pub(crate) trait StudyUploader {
+    /// Upload a PGN, returning the ids of the chapters created.
+    fn upload(&self, pgn: String) -> impl Future<Output = Result<Vec<String>, String>> + Send;
+}
+
+/// Uploads games as chapters of the configured Lichess study.
+pub(crate) struct LichessStudy {
+    client: Client,
+    host: String,
+    lichess: LichessConfig,
+}
+
+/// What Lichess answers to an import: the chapters created, of which we keep the ids.
+#[derive(Deserialize)]
+struct ImportedChapters {
+    chapters: Vec<ImportedChapter>,
+}
+
+#[derive(Deserialize)]
+struct ImportedChapter {
+    id: String,
+}
+
+impl LichessStudy {
+    pub(crate) fn new(lichess: LichessConfig) -> LichessStudy {
+        LichessStudy {
+            client: Client::new(),
+            host: LICHESS_HOST.to_string(),
+            lichess,
+        }
+    }
+}
+
+impl StudyUploader for LichessStudy {
+    async fn upload(&self, pgn: String) -> Result<Vec<String>, String> {
+        let study_id = self
+            .lichess
+            .study_id
+            .as_deref()
+            .ok_or("No study id configured")?;
+        let epoch = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let name = format!("{} {}", self.lichess.chapter_prefix, epoch);
+
+        let mut request = self
+            .client
+            .post(format!("{}/api/study/{study_id}/import-pgn", self.host))
+            .form(&[("name", name.as_str()), ("pgn", pgn.as_str())]);
+        if let Some(token) = &self.lichess.token {
+            request = request.bearer_auth(token.trim());
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("Can't reach Lichess: {e}"))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Lichess refused the import ({status}): {body}"));
+        }
+        let imported: ImportedChapters = response
+            .json()
+            .await
+            .map_err(|e| format!("Unexpected answer from Lichess: {e}"))?;
+        Ok(imported
+            .chapters
+            .into_iter()
+            .map(|chapter| chapter.id)
+            .collect())
+    }
+}
+
+/// Games waiting to be uploaded to the study, one file per game in `outbox/`.
+/// Files are named after a hash of the PGN, so that a game is never queued twice.
+#[derive(Clone)]
+pub(crate) struct Outbox {
+    archive_dir: PathBuf,
+    wake_up: Arc<Notify>,
+}
+
+impl Outbox {
+    pub(crate) fn new(archive_dir: &Path) -> Outbox {
+        Outbox {
+            archive_dir: archive_dir.to_path_buf(),
+            wake_up: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Queue a game for upload. Returns false if this game was already queued or uploaded.
+    pub(crate) fn queue(&self, pgn: &str) -> io::Result<bool> {
+        let hash = pgn_hash(pgn);
+        let path = self.outbox_dir().join(format!("{hash}.pgn"));
+        if path.exists() || self.uploaded_chapters(&hash)?.is_some() {
+            debug!("Game {hash} already queued or uploaded, skipping it");
+            return Ok(false);
+        }
+
+        // written aside then renamed: the uploader never sees half a game
+        fs::create_dir_all(self.outbox_dir())?;
+        let partial = path.with_extension("pgn.part");
+        fs::write(&partial, pgn)?;
+        fs::rename(&partial, &path)?;
+        debug!("Game queued for upload in {}", path.display());
+
+        self.wake_up.notify_one();
+        Ok(true)
+    }
+
+    /// Upload the queued games in the background until the program exits,
+    /// waiting longer and longer between attempts while uploads fail.
+    pub(crate) fn spawn_uploader<U>(&self, uploader: U) -> JoinHandle<()>
+    where
+        U: StudyUploader + Send + Sync + 'static,
+    {
+        let outbox = self.clone();
+        spawn(async move {
+            let mut backoff = MIN_BACKOFF;
+            loop {
+                let wait = match outbox.drain(&uploader).await {
+                    Ok(_) => {
+                        backoff = MIN_BACKOFF;
+                        IDLE_CHECK
+                    }
+                    Err(e) => {
+                        error!("Study upload failed, next try in {backoff:?}: {e}");
+                        let wait = backoff;
+                        backoff = next_backoff(backoff);
+                        wait
+                    }
+                };
+                // a newly queued game wakes us up early
+                let _ = timeout(wait, outbox.wake_up.notified()).await;
+            }
+        })
+    }
+
+    /// Upload the queued games, oldest first, stopping at the first failure.
+    /// Returns the number of games uploaded.
+    pub(crate) async fn drain<U: StudyUploader>(&self, uploader: &U) -> Result<usize, String> {
+        let mut pending: Vec<(std::time::SystemTime, PathBuf)> =
+            match fs::read_dir(self.outbox_dir()) {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "pgn"))
+                    .map(|entry| {
+                        let modified = entry
+                            .metadata()
+                            .and_then(|metadata| metadata.modified())
+                            .unwrap_or(std::time::UNIX_EPOCH);
+                        (modified, entry.path())
+                    })
+                    .collect(),
+                // no outbox yet: nothing to upload
+                Err(_) => return Ok(0),
+            };
+        pending.sort();
+
+        let mut uploaded = 0;
+        for (_, path) in pending {
+            let pgn = fs::read_to_string(&path)
+                .map_err(|e| format!("Can't read {}: {e}", path.display()))?;
+            let hash = pgn_hash(&pgn);
+
+            // uploaded before, but the file could not be removed then
+            if self
+                .uploaded_chapters(&hash)
+                .map_err(|e| e.to_string())?
+                .is_none()
+            {
+                let chapters = uploader.upload(pgn).await?;
+                info!("Game {hash} uploaded as chapters {chapters:?}");
+                self.log_upload(&hash, &chapters)
+                    .map_err(|e| format!("Can't record the upload of {hash}: {e}"))?;
+                uploaded += 1;
+            }
+
+            fs::remove_file(&path)
+                .map_err(|e| format!("Can't remove {} from the outbox: {e}", path.display()))?;
+        }
+
+        Ok(uploaded)
+    }
+
+    /// The chapter ids of a game already uploaded, `None` if it never was.
+    pub(crate) fn uploaded_chapters(&self, hash: &str) -> io::Result<Option<Vec<String>>> {
+        let log = match fs::read_to_string(self.archive_dir.join(UPLOADED_LOG)) {
+            Ok(log) => log,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        Ok(log.lines().find_map(|line| {
+            let (line_hash, chapters) = line.split_once('\t')?;
+            (line_hash == hash).then(|| {
+                chapters
+                    .split(',')
+                    .filter(|id| !id.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+        }))
+    }
+
+    fn log_upload(&self, hash: &str, chapters: &[String]) -> io::Result<()> {
+        fs::create_dir_all(&self.archive_dir)?;
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.archive_dir.join(UPLOADED_LOG))?;
+        writeln!(log, "{hash}\t{}", chapters.join(","))
+    }
+
+    fn outbox_dir(&self) -> PathBuf {
+        self.archive_dir.join(OUTBOX_DIR)
+    }
+}
+
+/// How long to wait after the next failure: twice longer, up to `MAX_BACKOFF`.
+fn next_backoff(backoff: Duration) -> Duration {
+    (backoff * 2).min(MAX_BACKOFF)
+}
+
+/// A stable hash of the game (64-bit FNV-1a), to recognize it across runs.
+fn pgn_hash(pgn: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in pgn.trim().bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    const PGN: &str = "[Event \"Offline game\"]\n\n1. e4 e5 2. Nf3 *\n";
+    const OTHER_PGN: &str = "[Event \"Offline game\"]\n\n1. d4 d5 *\n";
+
+    /// Stands in for the Lichess study: records the uploads, failing the first `failures` ones.
+    #[derive(Default)]
+    struct FakeStudy {
+        uploads: Mutex<Vec<String>>,
+        failures: Mutex<usize>,
+    }
+
+    impl FakeStudy {
+        fn failing(failures: usize) -> FakeStudy {
+            FakeStudy {
+                failures: Mutex::new(failures),
+                ..FakeStudy::default()
+            }
+        }
+
+        fn uploads(&self) -> Vec<String> {
+            self.uploads.lock().unwrap().clone()
+        }
+    }
+
+    impl StudyUploader for FakeStudy {
+        async fn upload(&self, pgn: String) -> Result<Vec<String>, String> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(String::from("503 Service Unavailable"));
+            }
+            let mut uploads = self.uploads.lock().unwrap();
+            uploads.push(pgn);
+            Ok(vec![format!("chapter{}", uploads.len())])
+        }
+    }
+
+    /// An empty archive directory of its own for each test.
+    fn archive_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("minac-outbox-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn the_same_game_is_uploaded_once() {
+        let dir = archive_dir("dedup");
+        let outbox = Outbox::new(&dir);
+        let study = FakeStudy::default();
+
+        assert!(outbox.queue(PGN).unwrap());
+        assert!(!outbox.queue(PGN).unwrap());
+        assert_eq!(outbox.drain(&study).await, Ok(1));
+
+        // still known once uploaded, thanks to uploaded.log
+        assert!(!outbox.queue(PGN).unwrap());
+        assert_eq!(outbox.drain(&study).await, Ok(0));
+        assert_eq!(study.uploads(), vec![PGN.to_string()]);
+        assert_eq!(
+            outbox.uploaded_chapters(&pgn_hash(PGN)).unwrap(),
+            Some(vec![String::from("chapter1")])
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_uploads_stay_queued_for_the_next_try() {
+        let dir = archive_dir("retry");
+        let outbox = Outbox::new(&dir);
+        let study = FakeStudy::failing(1);
+
+        outbox.queue(PGN).unwrap();
+        outbox.queue(OTHER_PGN).unwrap();
+        assert!(outbox.drain(&study).await.is_err());
+        assert!(study.uploads().is_empty());
+
+        assert_eq!(outbox.drain(&study).await, Ok(2));
+        assert_eq!(study.uploads().len(), 2);
+        assert_eq!(
+            outbox
+                .uploaded_chapters(&pgn_hash(PGN))
+                .unwrap()
+                .map(|c| c.len()),
+            Some(1)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_backoff_doubles_up_to_its_maximum() {
+        assert_eq!(next_backoff(MIN_BACKOFF), MIN_BACKOFF * 2);
+        let mut backoff = MIN_BACKOFF;
+        for _ in 0..20 {
+            backoff = next_backoff(backoff);
+        }
+        assert_eq!(backoff, MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn the_queue_survives_a_restart() {
+        let dir = archive_dir("restart");
+        Outbox::new(&dir).queue(PGN).unwrap();
+
+        // a new outbox on the same directory, as after restarting minac
+        let outbox = Outbox::new(&dir);
+        let study = FakeStudy::default();
+        assert!(!outbox.queue(PGN).unwrap());
+        assert_eq!(outbox.drain(&study).await, Ok(1));
+        assert_eq!(study.uploads(), vec![PGN.to_string()]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A local stand-in for Lichess: answers one request with `status` and `body`,
+    /// and hands the request over. Returns its address.
+    async fn serve_once(status: &str, body: &str) -> (String, oneshot::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = format!("http://{}", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {status}\r\ncontent-type: application/json\r\n\
+             content-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // the head, then as much of the body as announced
+            loop {
+                let read = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            let line = line.to_lowercase();
+                            let length = line.strip_prefix("content-length:")?;
+                            length.trim().parse::<usize>().ok()
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+                if read == 0 {
+                    break;
+                }
+            }
+            socket.write_all(response.as_bytes()).await.unwrap();
+            let _ = tx.send(String::from_utf8_lossy(&request).into_owned());
+        });
+        (host, rx)
+    }
+
+    /// The study, on the local server: no proxy of the environment in the way.
+    fn local_study(host: &str) -> LichessStudy {
+        LichessStudy {
+            client: Client::builder().no_proxy().build().unwrap(),
+            host: host.to_string(),
+            lichess: LichessConfig {
+                token: Some(String::from("lip_token ")),
+                study_id: Some(String::from("Stud1d42")),
+                chapter_prefix: String::from("Minac"),
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn lichess_study_imports_the_pgn_as_a_chapter() {
+        let (host, request) = serve_once(
+            "200 OK",
+            r#"{"chapters":[{"id":"Chap7er1","name":"Minac 1","players":[],"status":"*"}]}"#,
+        )
+        .await;
+        let study = local_study(&host);
+
+        assert_eq!(
+            study.upload(String::from("1. e4 e5 *")).await,
+            Ok(vec![String::from("Chap7er1")])
+        );
+
+        let request = request.await.unwrap();
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("POST /api/study/Stud1d42/import-pgn HTTP/1.1\r\n"));
+        let head = head.to_lowercase();
+        assert!(head.contains("authorization: bearer lip_token\r\n"));
+        assert!(head.contains("content-type: application/x-www-form-urlencoded"));
+        assert!(body.starts_with("name=Minac+"));
+        assert!(body.ends_with("&pgn=1.+e4+e5+*"));
+    }
+
+    #[tokio::test]
+    async fn lichess_study_reports_http_errors() {
+        let (host, _request) = serve_once("403 Forbidden", r#"{"error":"No such token"}"#).await;
+        let study = local_study(&host);
+
+        let error = study.upload(String::from("1. e4 e5 *")).await.unwrap_err();
+        assert!(error.contains("403"), "{error}");
+        assert!(error.contains("No such token"), "{error}");
+    }
+
+    #[tokio::test]
+    async fn lichess_study_failures_keep_the_game_queued() {
+        let (host, _request) = serve_once("500 Internal Server Error", "").await;
+        let dir = archive_dir("http");
+        let outbox = Outbox::new(&dir);
+        outbox.queue(PGN).unwrap();
+
+        let study = local_study(&host);
+        assert!(outbox.drain(&study).await.is_err());
+        assert!(!outbox.queue(PGN).unwrap());
+        assert_eq!(outbox.uploaded_chapters(&pgn_hash(PGN)).unwrap(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
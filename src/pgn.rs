@@ -0,0 +1,428 @@
+use crate::analysis::{GameAnalysis, MoveAnalysis};
+use crate::clock::{TimeControl, format_duration};
+use crate::notation::to_san;
+use crate::stockfish::Score;
+
+use chess::{Action, BitBoard, Board, ChessMove, Color, Game, GameResult, Piece};
+use std::time::Duration;
+
+/// A finished (or ongoing) game with everything the PGN needs on top of the moves:
+/// the players, the clock after each move and the side that lost on time, if any.
+pub(crate) struct GameRecord {
+    pub(crate) game: Game,
+    pub(crate) event: String,
+    pub(crate) site: String,
+    pub(crate) white: String,
+    pub(crate) black: String,
+    pub(crate) time_control: Option<TimeControl>,
+    /// when the game started
+    started: chrono::DateTime<chrono::Utc>,
+    start: Board,
+    /// time left for the side that moved, after each move
+    clocks: Vec<Option<Duration>>,
+    flagged: Option<Color>,
+    /// result known from elsewhere, e.g. Lichess for online games
+    result: Option<&'static str>,
+}
+
+impl GameRecord {
+    pub(crate) fn new(start: Board) -> GameRecord {
+        GameRecord {
+            game: Game::new_with_board(start),
+            event: String::from("?"),
+            site: String::from("minac"),
+            white: String::from("?"),
+            black: String::from("?"),
+            time_control: None,
+            started: chrono::Utc::now(),
+            start,
+            clocks: Vec::new(),
+            flagged: None,
+            result: None,
+        }
+    }
+
+    /// Make a move, recording the time left on the mover's clock.
+    /// Returns false if the move could not be made.
+    pub(crate) fn make_move(&mut self, chess_move: ChessMove, clock: Option<Duration>) -> bool {
+        let made = self.game.make_move(chess_move);
+        if made {
+            self.clocks.push(clock);
+        }
+        made
+    }
+
+    /// Replace the game by the one known to be right, e.g. by the server,
+    /// keeping the clocks of the moves both have in common.
+    pub(crate) fn resync(&mut self, start: Board, moves: &[ChessMove]) {
+        let common = if start == self.start {
+            self.moves()
+                .iter()
+                .zip(moves)
+                .take_while(|(ours, theirs)| ours == theirs)
+                .count()
+        } else {
+            0
+        };
+        self.clocks.truncate(common);
+
+        self.start = start;
+        self.game = Game::new_with_board(start);
+        for chess_move in moves {
+            if !self.game.make_move(*chess_move) {
+                break;
+            }
+        }
+        self.clocks.resize(self.moves().len(), None);
+    }
+
+    /// The flag of this side fell: the game is over.
+    pub(crate) fn flag(&mut self, color: Color) {
+        self.flagged = Some(color);
+    }
+
+    /// Set the result of a game that ended outside of the board, e.g. online by resignation.
+    pub(crate) fn set_result(&mut self, result: &'static str) {
+        self.result = Some(result);
+    }
+
+    pub(crate) fn is_over(&self) -> bool {
+        self.flagged.is_some() || self.result.is_some() || self.game.result().is_some()
+    }
+
+    pub(crate) fn start(&self) -> Board {
+        self.start
+    }
+
+    pub(crate) fn moves(&self) -> Vec<ChessMove> {
+        self.game
+            .actions()
+            .iter()
+            .filter_map(|action| match action {
+                Action::MakeMove(chess_move) => Some(*chess_move),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The PGN result: 1-0, 0-1, 1/2-1/2, or * if the game is not over.
+    pub(crate) fn result(&self) -> &'static str {
+        if let Some(color) = self.flagged {
+            // losing on time against a lone king (or king and minor piece) is a draw
+            return if has_mating_material(&self.game.current_position(), !color) {
+                match color {
+                    Color::White => "0-1",
+                    Color::Black => "1-0",
+                }
+            } else {
+                "1/2-1/2"
+            };
+        }
+
+        if let Some(result) = self.result {
+            return result;
+        }
+
+        match self.game.result() {
+            Some(GameResult::WhiteCheckmates) | Some(GameResult::BlackResigns) => "1-0",
+            Some(GameResult::BlackCheckmates) | Some(GameResult::WhiteResigns) => "0-1",
+            Some(GameResult::Stalemate)
+            | Some(GameResult::DrawAccepted)
+            | Some(GameResult::DrawDeclared) => "1/2-1/2",
+            None => "*",
+        }
+    }
+
+    /// The game in PGN, with the Seven Tag Roster headers and `%clk` comments. With an analysis,
+    /// moves also get `%eval` comments, NAGs for mistakes and the engine's line as a variation.
+    pub(crate) fn to_pgn(&self, analysis: Option<&GameAnalysis>) -> String {
+        let mut pgn = String::new();
+
+        let mut headers = vec![
+            ("Event", self.event.clone()),
+            ("Site", self.site.clone()),
+            (
+                "Date",
+                self.started
+                    .with_timezone(&chrono::Local)
+                    .format("%Y.%m.%d")
+                    .to_string(),
+            ),
+            ("Round", String::from("-")),
+            ("White", self.white.clone()),
+            ("Black", self.black.clone()),
+            ("Result", self.result().to_string()),
+            ("UTCDate", self.started.format("%Y.%m.%d").to_string()),
+            ("UTCTime", self.started.format("%H:%M:%S").to_string()),
+        ];
+        if self.start != Board::default() {
+            headers.push(("SetUp", String::from("1")));
+            headers.push(("FEN", self.start.to_string()));
+        }
+        if let Some(time_control) = &self.time_control {
+            headers.push(("TimeControl", time_control.pgn_tag()));
+        }
+        if self.flagged.is_some() {
+            headers.push(("Termination", String::from("time forfeit")));
+        }
+        for (name, value) in headers {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, escape_tag(&value)));
+        }
+        pgn.push('\n');
+
+        let mut board = self.start;
+        let mut move_number = 1;
+        let mut restate_number = true;
+        for (ply, chess_move) in self.moves().into_iter().enumerate() {
+            if board.side_to_move() == Color::White {
+                pgn.push_str(&format!("{move_number}. "));
+            } else if restate_number {
+                pgn.push_str(&format!("{move_number}... "));
+            }
+
+            pgn.push_str(&to_san(&board, chess_move));
+            let analysed = analysis.and_then(|analysis| analysis.moves.get(ply));
+            if let Some(judgement) = analysed.and_then(|analysed| analysed.judgement) {
+                pgn.push(' ');
+                pgn.push_str(judgement.nag());
+            }
+            pgn.push(' ');
+
+            // comments: evaluation after the move and clock
+            let mut comment = Vec::new();
+            if let Some(eval) = analysed.and_then(|analysed| analysed.eval) {
+                comment.push(format!("[%eval {}]", pgn_eval(eval)));
+            }
+            if let Some(Some(clock)) = self.clocks.get(ply) {
+                comment.push(format!("[%clk {}]", format_duration(*clock)));
+            }
+            if !comment.is_empty() {
+                pgn.push_str(&format!("{{ {} }} ", comment.join(" ")));
+            }
+
+            // what the engine would have played instead of a mistake
+            restate_number = !comment.is_empty();
+            if let Some(analysed) = analysed.filter(|analysed| analysed.judgement.is_some()) {
+                if let Some(variation) = variation(&board, move_number, analysed) {
+                    pgn.push_str(&variation);
+                    restate_number = true;
+                }
+            }
+
+            if board.side_to_move() == Color::Black {
+                move_number += 1;
+            }
+            board = board.make_move_new(chess_move);
+        }
+
+        pgn.push_str(self.result());
+        pgn.push('\n');
+        pgn
+    }
+}
+
+/// The engine's line instead of the analysed move, e.g. `( 12. Nd2 Nf6 13. c3 ) `.
+fn variation(board: &Board, move_number: u32, analysed: &MoveAnalysis) -> Option<String> {
+    if analysed.best_line.first() == Some(&analysed.chess_move) {
+        return None;
+    }
+
+    let mut board = *board;
+    let mut move_number = move_number;
+    let mut line = Vec::new();
+    for (i, chess_move) in analysed.best_line.iter().enumerate() {
+        // the line comes from the engine, stop at the first move we don't agree with
+        if !board.legal(*chess_move) {
+            break;
+        }
+        if board.side_to_move() == Color::White {
+            line.push(format!("{move_number}."));
+        } else if i == 0 {
+            line.push(format!("{move_number}..."));
+        }
+        line.push(to_san(&board, *chess_move));
+
+        if board.side_to_move() == Color::Black {
+            move_number += 1;
+        }
+        board = board.make_move_new(*chess_move);
+    }
+
+    if line.is_empty() {
+        None
+    } else {
+        Some(format!("( {} ) ", line.join(" ")))
+    }
+}
+
+/// A tag value as the PGN standard wants it: backslashes and quotes escaped with a backslash.
+fn escape_tag(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Evaluation in pawns as Lichess writes it in `%eval`, e.g. `0.35`, `-1.20` or `#-3`.
+fn pgn_eval(score: Score) -> String {
+    match score {
+        Score::Centipawns(cp) => format!("{:.2}", f64::from(cp) / 100.0),
+        Score::Mate(moves) => format!("#{moves}"),
+    }
+}
+
+/// Whether this side has enough material to ever checkmate.
+fn has_mating_material(board: &Board, color: Color) -> bool {
+    let pieces = board.color_combined(color);
+    let heavy = board.pieces(Piece::Pawn) | board.pieces(Piece::Rook) | board.pieces(Piece::Queen);
+    let minors = board.pieces(Piece::Knight) | board.pieces(Piece::Bishop);
+
+    (pieces & heavy) != BitBoard::new(0) || (pieces & minors).popcnt() > 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::Judgement;
+    use crate::notation::parse_move;
+    use std::str::FromStr;
+
+    /// A game from `fen` with these moves, each with the clock left if given.
+    fn game(fen: &str, moves: &[(&str, Option<u64>)]) -> GameRecord {
+        let mut record = GameRecord::new(Board::from_str(fen).unwrap());
+        for (san, clock) in moves {
+            let chess_move = parse_move(&record.game.current_position(), san).unwrap();
+            assert!(record.make_move(chess_move, clock.map(Duration::from_secs)));
+        }
+        record
+    }
+
+    /// The PGN without its headers.
+    fn movetext(pgn: &str) -> &str {
+        pgn.split_once("\n\n").unwrap().1
+    }
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    // kings and a white pawn, black to move
+    const PAWN_ENDING: &str = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 1";
+
+    #[test]
+    fn numbers_the_moves() {
+        let record = game(START, &[("e4", None), ("e5", None), ("Nf3", None)]);
+        assert_eq!(movetext(&record.to_pgn(None)), "1. e4 e5 2. Nf3 *\n");
+    }
+
+    #[test]
+    fn restates_the_number_after_a_comment() {
+        let record = game(START, &[("e4", Some(300)), ("e5", Some(298))]);
+        assert_eq!(
+            movetext(&record.to_pgn(None)),
+            "1. e4 { [%clk 0:05:00] } 1... e5 { [%clk 0:04:58] } *\n"
+        );
+    }
+
+    #[test]
+    fn custom_start_gets_setup_and_fen() {
+        let record = game(PAWN_ENDING, &[("Kd7", None), ("e4", None)]);
+        let pgn = record.to_pgn(None);
+        assert!(pgn.contains("[SetUp \"1\"]\n"));
+        assert!(pgn.contains(&format!("[FEN \"{}\"]\n", record.start())));
+        assert_eq!(movetext(&pgn), "1... Kd7 2. e4 *\n");
+
+        let pgn = GameRecord::new(Board::default()).to_pgn(None);
+        assert!(!pgn.contains("[SetUp"));
+        assert!(!pgn.contains("[FEN"));
+    }
+
+    #[test]
+    fn analysis_adds_evals_nags_and_variations() {
+        let record = game(
+            START,
+            &[("e4", None), ("e5", None), ("Qh5", None), ("Nc6", None)],
+        );
+        let moves = record.moves();
+        let mut board = record.start();
+        let mut analysed = Vec::new();
+        for (ply, (eval, judgement)) in [
+            (Score::Centipawns(30), None),
+            (Score::Centipawns(35), None),
+            (Score::Mate(-3), Some(Judgement::Blunder)),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            // the engine prefers 2. Nf3 Nc6 to the blunder
+            let best_line = match ply {
+                2 => {
+                    let nf3 = parse_move(&board, "Nf3").unwrap();
+                    let nc6 = parse_move(&board.make_move_new(nf3), "Nc6").unwrap();
+                    vec![nf3, nc6]
+                }
+                _ => vec![moves[ply]],
+            };
+            analysed.push(MoveAnalysis {
+                chess_move: moves[ply],
+                san: to_san(&board, moves[ply]),
+                side: board.side_to_move(),
+                eval: Some(eval),
+                best_move: best_line.first().copied(),
+                best_san: None,
+                best_line,
+                centipawn_loss: 0,
+                judgement,
+            });
+            board = board.make_move_new(moves[ply]);
+        }
+        let analysis = GameAnalysis { moves: analysed };
+
+        assert_eq!(
+            movetext(&record.to_pgn(Some(&analysis))),
+            "1. e4 { [%eval 0.30] } 1... e5 { [%eval 0.35] } 2. Qh5 $4 { [%eval #-3] } \
+             ( 2. Nf3 Nc6 ) 2... Nc6 *\n"
+        );
+    }
+
+    #[test]
+    fn flag_fall_result() {
+        let mut record = game(START, &[("e4", None)]);
+        record.flag(Color::White);
+        let pgn = record.to_pgn(None);
+        assert_eq!(record.result(), "0-1");
+        assert!(pgn.contains("[Result \"0-1\"]\n"));
+        assert!(pgn.contains("[Termination \"time forfeit\"]\n"));
+
+        // a lone king can't win on time
+        let mut record = game(PAWN_ENDING, &[]);
+        record.flag(Color::Black);
+        assert_eq!(record.result(), "1-0");
+        let mut record = game(PAWN_ENDING, &[]);
+        record.flag(Color::White);
+        assert_eq!(record.result(), "1/2-1/2");
+    }
+
+    #[test]
+    fn resync_keeps_the_clocks_of_the_common_moves() {
+        let mut record = game(START, &[("e4", Some(300)), ("e5", Some(298))]);
+        let e4 = record.moves()[0];
+        let d5 = parse_move(&record.start().make_move_new(e4), "d5").unwrap();
+        record.resync(record.start(), &[e4, d5]);
+        assert_eq!(
+            movetext(&record.to_pgn(None)),
+            "1. e4 { [%clk 0:05:00] } 1... d5 *\n"
+        );
+
+        // another start: nothing in common
+        let start = Board::from_str(PAWN_ENDING).unwrap();
+        record.resync(start, &[]);
+        assert_eq!(record.start(), start);
+        assert!(record.moves().is_empty());
+    }
+
+    #[test]
+    fn escapes_quotes_and_backslashes_in_tags() {
+        assert_eq!(escape_tag("zoug"), "zoug");
+        assert_eq!(
+            escape_tag(r#"The "Immortal" game"#),
+            r#"The \"Immortal\" game"#
+        );
+        assert_eq!(escape_tag(r"C:\games"), r"C:\\games");
+        assert_eq!(escape_tag(r#"\""#), r#"\\\""#);
+    }
+}
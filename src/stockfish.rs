@@ -1,47 +1,528 @@
-use log::{debug, info, trace};
+use chess::{ChessMove, Color};
+use log::{debug, info, trace, warn};
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use tokio::io::AsyncWriteExt;
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
 use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::spawn;
 use tokio::sync::mpsc;
+use tokio::time::timeout;
 use vampirc_uci::{
-    Duration, MessageList, UciMessage, UciOptionConfig, UciTimeControl, parse_with_unknown,
+    Duration, MessageList, UciInfoAttribute, UciMessage, UciOptionConfig, UciSearchControl,
+    UciTimeControl, parse_with_unknown,
 };
 
+use crate::clock::{Clock, Increment};
+use crate::config::EngineConfig;
+use crate::notation::to_uci;
 use crate::online::commands::{StockfishInput, StockfishOutput};
 
-pub(crate) async fn launch_stockfish() -> Child {
-    let stockfish = Command::new("../stockfish16/stockfish-ubuntu-x86-64")
+// binary name looked up in $PATH when no explicit path is given
+const STOCKFISH_BINARY: &str = "stockfish";
+// how long the engine has to answer `uci` and `isready`
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How strong the engine plays.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum EngineStrength {
+    /// target rating, sent as UCI_LimitStrength and UCI_Elo
+    Elo(i64),
+    /// Stockfish's Skill Level, from 0 to 20
+    SkillLevel(i64),
+}
+
+/// When the engine stops searching for its next move.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum SearchLimit {
+    Depth(u8),
+    Nodes(u64),
+    /// milliseconds per move
+    MoveTime(u64),
+}
+
+impl EngineStrength {
+    /// The options setting this strength. The rating is kept within the UCI_Elo bounds
+    /// advertised by the engine, wherever it comes from: Stockfish refuses one below 1320.
+    fn to_uci(self, elo_range: Option<(i64, i64)>) -> Vec<UciMessage> {
+        let (limit_strength, name, value) = match self {
+            EngineStrength::Elo(elo) => {
+                let elo = elo_range.map_or(elo, |(min, max)| elo.clamp(min, max));
+                ("true", "UCI_Elo", elo)
+            }
+            EngineStrength::SkillLevel(level) => ("false", "Skill Level", level),
+        };
+        vec![
+            UciMessage::SetOption {
+                name: String::from("UCI_LimitStrength"),
+                value: Some(String::from(limit_strength)),
+            },
+            UciMessage::SetOption {
+                name: String::from(name),
+                value: Some(value.to_string()),
+            },
+        ]
+    }
+}
+
+impl SearchLimit {
+    /// The `go` command for this limit. In timed games the clocks replace the
+    /// fixed time per move, while depth and nodes limits still apply.
+    fn to_uci(self, clock: Option<UciTimeControl>) -> UciMessage {
+        match self {
+            SearchLimit::Depth(depth) => UciMessage::Go {
+                time_control: clock,
+                search_control: Some(UciSearchControl::depth(depth)),
+            },
+            SearchLimit::Nodes(nodes) => UciMessage::Go {
+                time_control: clock,
+                search_control: Some(UciSearchControl::nodes(nodes)),
+            },
+            SearchLimit::MoveTime(ms) => UciMessage::Go {
+                time_control: clock.or(Some(UciTimeControl::MoveTime(Duration::milliseconds(
+                    ms as i64,
+                )))),
+                search_control: None,
+            },
+        }
+    }
+}
+
+/// The `wtime`/`btime`/`winc`/`binc`/`movestogo` arguments of `go`, from our clock.
+/// A Bronstein delay gives back at most the time used, like an increment the engine can count on.
+/// A simple delay adds nothing to the clock: the engine gets no increment, and budgets
+/// the time it has, the delay being a safety margin.
+pub(crate) fn clock_time_control(clock: &Clock, side_to_move: Color) -> UciTimeControl {
+    let increment = |color| match clock.increment(color) {
+        Increment::Fischer(d) | Increment::Bronstein(d) => {
+            Some(Duration::milliseconds(d.as_millis() as i64))
+        }
+        Increment::Delay(_) | Increment::None => None,
+    };
+
+    UciTimeControl::TimeLeft {
+        white_time: Some(Duration::milliseconds(
+            clock.remaining(Color::White).as_millis() as i64,
+        )),
+        black_time: Some(Duration::milliseconds(
+            clock.remaining(Color::Black).as_millis() as i64,
+        )),
+        white_increment: increment(Color::White),
+        black_increment: increment(Color::Black),
+        moves_to_go: clock
+            .moves_to_go(side_to_move)
+            .map(|moves| moves.min(u8::MAX.into()) as u8),
+    }
+}
+
+/// An engine evaluation, from the point of view of the side it applies to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Score {
+    Centipawns(i32),
+    /// mate in that many moves, negative when getting mated
+    Mate(i8),
+}
+
+impl Score {
+    /// Engines score from the side to move's point of view: flip it for black
+    /// to get the score from white's point of view.
+    pub(crate) fn for_white(self, side_to_move: Color) -> Score {
+        match (self, side_to_move) {
+            (score, Color::White) => score,
+            (Score::Centipawns(cp), Color::Black) => Score::Centipawns(-cp),
+            (Score::Mate(moves), Color::Black) => Score::Mate(-moves),
+        }
+    }
+
+    /// The score in centipawns, mates counting as more than any material advantage
+    /// (the closer the mate, the higher).
+    pub(crate) fn to_centipawns(self) -> i32 {
+        match self {
+            Score::Centipawns(cp) => cp,
+            Score::Mate(moves) => {
+                let cp = (21 - i32::from(moves.unsigned_abs()).min(10)) * 100;
+                if moves < 0 { -cp } else { cp }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Score::Centipawns(cp) => write!(f, "{:+.2}", f64::from(*cp) / 100.0),
+            Score::Mate(moves) => write!(f, "#{moves}"),
+        }
+    }
+}
+
+/// Whether the score of a search info is exact or only a bound.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ScoreBound {
+    Lower,
+    Upper,
+}
+
+/// The content of an `info` line sent by the engine while it searches.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct SearchInfo {
+    pub(crate) depth: Option<u8>,
+    pub(crate) seldepth: Option<u8>,
+    /// from the point of view of the side to move
+    pub(crate) score: Option<Score>,
+    pub(crate) bound: Option<ScoreBound>,
+    pub(crate) nodes: Option<u64>,
+    pub(crate) nps: Option<u64>,
+    /// how full the hash table is, in permill
+    pub(crate) hashfull: Option<u16>,
+    pub(crate) multipv: Option<u16>,
+    pub(crate) pv: Vec<ChessMove>,
+}
+
+impl SearchInfo {
+    fn from_attributes(attributes: Vec<UciInfoAttribute>) -> SearchInfo {
+        let mut info = SearchInfo::default();
+        for attribute in attributes {
+            match attribute {
+                UciInfoAttribute::Depth(depth) => info.depth = Some(depth),
+                UciInfoAttribute::SelDepth(seldepth) => info.seldepth = Some(seldepth),
+                UciInfoAttribute::Score {
+                    cp,
+                    mate,
+                    lower_bound,
+                    upper_bound,
+                } => {
+                    info.score = match (mate, cp) {
+                        (Some(moves), _) => Some(Score::Mate(moves)),
+                        (None, Some(cp)) => Some(Score::Centipawns(cp)),
+                        (None, None) => None,
+                    };
+                    info.bound = match (lower_bound, upper_bound) {
+                        (Some(true), _) => Some(ScoreBound::Lower),
+                        (_, Some(true)) => Some(ScoreBound::Upper),
+                        _ => None,
+                    };
+                }
+                UciInfoAttribute::Nodes(nodes) => info.nodes = Some(nodes),
+                UciInfoAttribute::Nps(nps) => info.nps = Some(nps),
+                UciInfoAttribute::HashFull(hashfull) => info.hashfull = Some(hashfull),
+                UciInfoAttribute::MultiPv(multipv) => info.multipv = Some(multipv),
+                UciInfoAttribute::Pv(moves) => info.pv = moves,
+                _ => (),
+            };
+        }
+        info
+    }
+
+    /// An exact evaluation of the main line, as opposed to a bound or a secondary line.
+    pub(crate) fn is_exact(&self) -> bool {
+        self.score.is_some() && self.bound.is_none() && self.multipv.unwrap_or(1) == 1
+    }
+}
+
+/// Find the engine binary: the configured path if any, otherwise the first
+/// `stockfish` executable found in `$PATH`.
+pub(crate) fn find_stockfish(engine: &EngineConfig) -> Option<PathBuf> {
+    if let Some(path) = &engine.path {
+        return Some(path.clone());
+    }
+
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(STOCKFISH_BINARY))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// A file we are allowed to run: a `stockfish` file without the permission is skipped.
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+pub(crate) async fn launch_stockfish(engine: &EngineConfig) -> io::Result<Child> {
+    let path = find_stockfish(engine).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "Stockfish not found: set the engine path in the config file, or put a `{}` binary in your $PATH",
+                STOCKFISH_BINARY
+            ),
+        )
+    })?;
+
+    let stockfish = Command::new(&path)
+        .args(&engine.args)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
+        .kill_on_drop(true)
         .spawn()
-        .expect("Can't spawn Stockfish process.");
+        .map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Can't spawn Stockfish process {}: {e}", path.display()),
+            )
+        })?;
+
+    info!("Stockfish launched from {}.", path.display());
+    Ok(stockfish)
+}
 
-    info!("Stockfish launched.");
-    stockfish
+/// A Stockfish process that went through the UCI handshake and is configured,
+/// ready to play.
+pub(crate) struct EngineSession {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    pub(crate) name: Option<String>,
+    pub(crate) author: Option<String>,
+    /// options advertised by the engine during the handshake
+    pub(crate) options: Vec<UciOptionConfig>,
+}
+
+/// The channels used to talk to an engine running in the background.
+/// The engine process is killed when this is dropped.
+pub(crate) struct EngineHandle {
+    pub(crate) tx: mpsc::Sender<StockfishInput>,
+    pub(crate) rx: mpsc::Receiver<StockfishOutput>,
+    _child: Child,
+}
+
+impl EngineSession {
+    /// Launch the engine, do the `uci`/`uciok` handshake, send the configured
+    /// options and wait for `readyok`.
+    pub(crate) async fn start(engine: &EngineConfig) -> io::Result<EngineSession> {
+        let mut child = launch_stockfish(engine).await?;
+
+        // take the stdin and stdout of the stockfish child process
+        let stdin = child.stdin.take().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Stockfish child has no stdin handle",
+            )
+        })?;
+        let stdout = child.stdout.take().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Stockfish child has no stdout handle",
+            )
+        })?;
+
+        let mut session = EngineSession {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+            name: None,
+            author: None,
+            options: Vec::new(),
+        };
+
+        timeout(HANDSHAKE_TIMEOUT, session.handshake())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Stockfish never sent uciok"))??;
+        info!(
+            "Engine {} by {} ready, {} options advertised",
+            session.name.as_deref().unwrap_or("?"),
+            session.author.as_deref().unwrap_or("?"),
+            session.options.len()
+        );
+
+        session.configure(engine).await?;
+        Ok(session)
+    }
+
+    /// Spawn the tasks reading from and writing to the engine, and return the
+    /// channels to communicate with them.
+    pub(crate) fn spawn(self) -> EngineHandle {
+        // channels for stockfish in/out
+        let (tx_in, rx_in) = mpsc::channel(10);
+        let (tx_out, rx_out) = mpsc::channel(40);
+
+        // tasks for handling stockfish in/out
+        let elo_range = self.elo_range();
+        spawn(send_move_to_stockfish(self.stdin, rx_in, elo_range));
+        spawn(receive_stockfish_best_move(self.stdout, tx_out));
+
+        EngineHandle {
+            tx: tx_in,
+            rx: rx_out,
+            _child: self.child,
+        }
+    }
+
+    async fn handshake(&mut self) -> io::Result<()> {
+        self.send(vec![UciMessage::Uci]).await?;
+
+        // collect the id and option declarations until uciok
+        while let Some(line) = self.stdout.next_line().await? {
+            for uci_message in parse_with_unknown(&line) {
+                trace!("handshake uci_message: {:?}", uci_message);
+                match uci_message {
+                    UciMessage::Id { name, author } => {
+                        if name.is_some() {
+                            self.name = name;
+                        }
+                        if author.is_some() {
+                            self.author = author;
+                        }
+                    }
+                    UciMessage::Option(option) => self.options.push(option),
+                    UciMessage::UciOk => return Ok(()),
+                    _ => (),
+                };
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Stockfish exited during the UCI handshake",
+        ))
+    }
+
+    async fn configure(&mut self, engine: &EngineConfig) -> io::Result<()> {
+        let mut settings = vec![
+            ("Threads", engine.threads.to_string()),
+            ("Hash", engine.hash.to_string()),
+            ("Skill Level", engine.skill_level.to_string()),
+        ];
+        match engine.elo {
+            Some(elo) => {
+                settings.push(("UCI_LimitStrength", String::from("true")));
+                settings.push(("UCI_Elo", elo.to_string()));
+            }
+            None => settings.push(("UCI_LimitStrength", String::from("false"))),
+        };
+
+        // only set the options the engine told us about
+        let mut messages = MessageList::new();
+        for (name, value) in settings {
+            match self.option(name) {
+                Some(option) => messages.push(UciMessage::SetOption {
+                    name: String::from(name),
+                    value: Some(clamp_spin(option, value)),
+                }),
+                None => warn!("The engine has no {name} option, not setting it"),
+            };
+        }
+        messages.push(UciMessage::IsReady);
+        self.send(messages).await?;
+
+        timeout(HANDSHAKE_TIMEOUT, self.wait_for_ready())
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Stockfish never sent readyok"))?
+    }
+
+    async fn wait_for_ready(&mut self) -> io::Result<()> {
+        while let Some(line) = self.stdout.next_line().await? {
+            if parse_with_unknown(&line)
+                .iter()
+                .any(|m| matches!(m, UciMessage::ReadyOk))
+            {
+                return Ok(());
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Stockfish exited before sending readyok",
+        ))
+    }
+
+    /// The UCI_Elo bounds advertised by the engine, if it supports limiting its strength.
+    pub(crate) fn elo_range(&self) -> Option<(i64, i64)> {
+        match self.option("UCI_Elo") {
+            Some(UciOptionConfig::Spin { min, max, .. }) => {
+                Some((min.unwrap_or(i64::MIN), max.unwrap_or(i64::MAX)))
+            }
+            _ => None,
+        }
+    }
+
+    fn option(&self, name: &str) -> Option<&UciOptionConfig> {
+        self.options
+            .iter()
+            .find(|option| option_name(option) == name)
+    }
+
+    async fn send(&mut self, messages: MessageList) -> io::Result<()> {
+        let mut messages_str = String::new();
+        for m in messages {
+            messages_str.push_str(&m.to_string());
+            messages_str.push_str("\n");
+        }
+
+        debug!("Sending these messages to Stockfish: {}", messages_str);
+        self.stdin.write_all(messages_str.as_bytes()).await?;
+        self.stdin.flush().await
+    }
+}
+
+/// The `position` command for a game: its starting position, and its moves written by `to_uci`.
+fn position_line(start_fen: &str, moves: &[ChessMove]) -> String {
+    let mut line = format!("position fen {start_fen}");
+    if !moves.is_empty() {
+        line.push_str(" moves");
+        for m in moves {
+            line.push(' ');
+            line.push_str(&to_uci(*m));
+        }
+    }
+    line
+}
+
+fn option_name(option: &UciOptionConfig) -> &str {
+    match option {
+        UciOptionConfig::Check { name, .. }
+        | UciOptionConfig::Spin { name, .. }
+        | UciOptionConfig::Combo { name, .. }
+        | UciOptionConfig::Button { name }
+        | UciOptionConfig::String { name, .. } => name,
+    }
+}
+
+/// Keep numeric values within the bounds advertised by the engine,
+/// e.g. Stockfish refuses an UCI_Elo below 1320.
+fn clamp_spin(option: &UciOptionConfig, value: String) -> String {
+    match (option, value.parse::<i64>()) {
+        (UciOptionConfig::Spin { min, max, .. }, Ok(v)) => {
+            let v = min.map_or(v, |min| v.max(min));
+            let v = max.map_or(v, |max| v.min(max));
+            v.to_string()
+        }
+        _ => value,
+    }
 }
 
 pub(crate) async fn receive_stockfish_best_move(
-    stockfish_out: ChildStdout,
+    mut reader: Lines<BufReader<ChildStdout>>,
     tx: mpsc::Sender<StockfishOutput>,
 ) {
-    // handle current stdout from stockfish to get BestMove
-    debug!("ChildStdout recevied: {:?}", stockfish_out);
-    let mut reader = BufReader::new(stockfish_out).lines();
-
-    while let Some(next_line) = reader.next_line().await.unwrap() {
+    // handle current stdout from stockfish to get evaluations and BestMove
+    // a read error is the end of the engine output, like the process exiting
+    while let Ok(Some(next_line)) = reader.next_line().await {
         for uci_message in parse_with_unknown(&next_line) {
             trace!("uci_message: {:?}", uci_message);
-            match uci_message {
-                UciMessage::BestMove { best_move, ponder } => {
-                    tx.send(StockfishOutput::StockfishBestMove {
-                        chess_move: best_move,
-                    })
-                    .await
-                    .unwrap();
+            let output = match uci_message {
+                // only forward the search progress, not currmove or string infos
+                UciMessage::Info(attributes) => {
+                    let info = SearchInfo::from_attributes(attributes);
+                    if info.score.is_none() && info.pv.is_empty() {
+                        continue;
+                    }
+                    StockfishOutput::StockfishInfo { info }
                 }
-                _ => (),
+                UciMessage::BestMove { best_move, .. } => StockfishOutput::StockfishBestMove {
+                    chess_move: best_move,
+                },
+                _ => continue,
             };
+
+            // the game is over (flag fall, resignation) and the handle dropped: stop reading
+            if tx.send(output).await.is_err() {
+                debug!("Nobody listens to the engine anymore, stop reading its output");
+                return;
+            }
         }
     }
 }
@@ -49,9 +530,13 @@ pub(crate) async fn receive_stockfish_best_move(
 pub(crate) async fn send_move_to_stockfish(
     mut stockfish_in: ChildStdin,
     mut rx: mpsc::Receiver<StockfishInput>,
+    elo_range: Option<(i64, i64)>,
 ) {
     debug!("ChildStdin recevied: {:?}", stockfish_in);
 
+    // search limit used for every move, until the next Configure command
+    let mut search_limit = SearchLimit::MoveTime(1000);
+
     // receive stockfish input commands
     while let Some(cmd) = rx.recv().await {
         // the messages vec and string we'll use to send the commands to stockfish
@@ -59,51 +544,35 @@ pub(crate) async fn send_move_to_stockfish(
         let mut messages_str = String::new();
 
         match cmd {
-            StockfishInput::Configure { level, depth } => {
+            StockfishInput::Configure { strength, limit } => {
                 // this is the first expected command, we hence configure a new uci game from the startpos
-                // and we specify the level and depth options received
-                messages.push(UciMessage::Uci);
+                // with the strength received, and keep the search limit for the next moves
                 messages.push(UciMessage::UciNewGame);
-                messages.push(UciMessage::Option(UciOptionConfig::Spin {
-                    name: String::from("Skill Level"),
-                    default: Some(level),
-                    min: Some(level),
-                    max: Some(level),
-                }));
+                messages.extend(strength.to_uci(elo_range));
                 messages.push(UciMessage::Position {
                     startpos: true,
                     fen: None,
                     moves: Vec::new(),
                 });
                 messages.push(UciMessage::IsReady);
+                search_limit = limit;
             }
-            StockfishInput::PlayerMove { chess_move, fen } => {
+            StockfishInput::Go {
+                start_fen,
+                moves,
+                time_control,
+            } => {
                 debug!(
-                    "Received UCI move from player {}{}, sending it to stockfish",
-                    chess_move.get_source(),
-                    chess_move.get_dest()
+                    "Asking stockfish for a move after {} moves, clocks: {:?}",
+                    moves.len(),
+                    time_control
                 );
-                // send current position with the move we want to play
-                let m = UciMessage::Position {
-                    startpos: false,
-                    fen: Some(vampirc_uci::UciFen(fen)),
-                    moves: vec![chess_move],
-                };
-                messages.push(m);
-
-                // tell stockfish to start pondering for 5 secs
-                let time_control = UciTimeControl::TimeLeft {
-                    white_time: Some(Duration::seconds(5)),
-                    black_time: Some(Duration::seconds(5)),
-                    white_increment: None,
-                    black_increment: None,
-                    moves_to_go: Some(90),
-                };
+                // send the whole game, so that the engine knows about repetitions
+                messages_str.push_str(&position_line(&start_fen, &moves));
+                messages_str.push_str("\n");
 
-                messages.push(UciMessage::Go {
-                    time_control: Some(time_control),
-                    search_control: None,
-                });
+                // tell stockfish to start searching within the configured limit
+                messages.push(search_limit.to_uci(time_control));
             }
         }
 
@@ -123,3 +592,88 @@ pub(crate) async fn send_move_to_stockfish(
         trace!("Message sent, stdin flushed");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess::{Piece, Square};
+
+    /// The search info of an `info` line, as the engine reader parses it.
+    fn info(line: &str) -> SearchInfo {
+        match parse_with_unknown(line).into_iter().next() {
+            Some(UciMessage::Info(attributes)) => SearchInfo::from_attributes(attributes),
+            other => panic!("Not an info line: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_an_exact_centipawn_score_and_its_line() {
+        let info = info(
+            "info depth 18 seldepth 24 multipv 1 score cp 35 nodes 812345 nps 1203456 \
+             hashfull 312 tbhits 0 time 675 pv e2e4 e7e5 g1f3",
+        );
+        assert_eq!(info.depth, Some(18));
+        assert_eq!(info.seldepth, Some(24));
+        assert_eq!(info.score, Some(Score::Centipawns(35)));
+        assert_eq!(info.bound, None);
+        assert_eq!(info.nodes, Some(812345));
+        assert_eq!(info.nps, Some(1203456));
+        assert_eq!(info.hashfull, Some(312));
+        assert_eq!(info.multipv, Some(1));
+        assert_eq!(
+            info.pv,
+            vec![
+                ChessMove::new(Square::E2, Square::E4, None),
+                ChessMove::new(Square::E7, Square::E5, None),
+                ChessMove::new(Square::G1, Square::F3, None),
+            ]
+        );
+        assert!(info.is_exact());
+    }
+
+    #[test]
+    fn parses_mate_scores() {
+        let info = info("info depth 12 score mate -3 nodes 1000 pv h7h8q");
+        assert_eq!(info.score, Some(Score::Mate(-3)));
+        assert_eq!(
+            info.pv,
+            vec![ChessMove::new(Square::H7, Square::H8, Some(Piece::Queen))]
+        );
+        assert!(info.is_exact());
+    }
+
+    #[test]
+    fn bounds_and_secondary_lines_are_not_exact() {
+        let lower = info("info depth 20 score cp 41 lowerbound nodes 5000 pv d2d4");
+        assert_eq!(lower.bound, Some(ScoreBound::Lower));
+        assert!(!lower.is_exact());
+
+        let upper = info("info depth 20 score cp -12 upperbound nodes 5000 pv d2d4");
+        assert_eq!(upper.bound, Some(ScoreBound::Upper));
+        assert!(!upper.is_exact());
+
+        let second = info("info depth 20 multipv 2 score cp 20 nodes 5000 pv c2c4");
+        assert_eq!(second.multipv, Some(2));
+        assert!(!second.is_exact());
+
+        // no score at all, e.g. the current move being searched
+        let currmove = info("info depth 5 currmove e2e4 currmovenumber 1");
+        assert_eq!(currmove.score, None);
+        assert!(!currmove.is_exact());
+    }
+
+    #[test]
+    fn position_line_writes_the_moves_in_uci() {
+        let fen = "8/P6k/8/8/8/8/8/K7 w - - 0 1";
+        assert_eq!(position_line(fen, &[]), format!("position fen {fen}"));
+
+        let moves = [
+            ChessMove::new(Square::A7, Square::A8, Some(Piece::Queen)),
+            ChessMove::new(Square::H7, Square::G6, None),
+        ];
+        assert_eq!(
+            position_line(fen, &moves),
+            format!("position fen {fen} moves a7a8q h7g6")
+        );
+    }
+}
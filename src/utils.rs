@@ -1,27 +1,43 @@
-use chess::Color;
-use std::io::Write;
-use std::io::{stdin, stdout};
+use chess::{Board, Color};
+use lichess_api::model::challenges::DeclineReason;
+use std::time::Duration;
 
+use crate::clock::TimeControl;
+use crate::input::{read_line_blocking, set_position};
 use crate::online::commands::MoveOption;
+use crate::stockfish::{EngineStrength, SearchLimit};
 
-pub(crate) fn ask_for_move() -> (String, Option<MoveOption>) {
-    println!("Your turn. Enter the SAN move. Example: Nf3");
-    print!(">>> ");
-    let _ = stdout().flush();
-
-    let mut line_parsed = String::new();
-    stdin()
-        .read_line(&mut line_parsed)
-        .expect("IO Eroor: failed to read line");
+/// Ask for a move in this position, giving up after `timeout` (used when a clock is running).
+pub(crate) fn ask_for_move_within(
+    board: &Board,
+    timeout: Option<Duration>,
+) -> Option<(String, Option<MoveOption>)> {
+    println!("Your turn. Enter the move. Example: Nf3, or just f3 if only one piece can go there");
+    set_position(Some(*board));
+    let command = read_input_within(timeout);
+    set_position(None);
+    Some(parse_move_input(&command?))
+}
 
-    let command = line_parsed.trim();
+/// Split what was typed at the move prompt into the move and the command that comes with it.
+pub(crate) fn parse_move_input(command: &str) -> (String, Option<MoveOption>) {
     let mut option = None;
 
-    if command.ends_with("DRAW") {
-        option = Some(MoveOption::Draw);
-    } else if command == "RESIGN" {
-        option = Some(MoveOption::Resign);
-    }
+    // commands without a move first: NODRAW also ends with DRAW
+    match command {
+        "DRAW" => option = Some(MoveOption::AnswerDraw(true)),
+        "NODRAW" => option = Some(MoveOption::AnswerDraw(false)),
+        "TAKEBACK" => option = Some(MoveOption::AnswerTakeback(true)),
+        "NOTAKEBACK" => option = Some(MoveOption::AnswerTakeback(false)),
+        "ABORT" => option = Some(MoveOption::Abort),
+        "CLAIM" => option = Some(MoveOption::ClaimVictory),
+        "RESIGN" => option = Some(MoveOption::Resign),
+        _ if command.starts_with("/say ") => {
+            option = Some(MoveOption::Say(command["/say ".len()..].trim().to_string()));
+        }
+        _ if command.ends_with("DRAW") => option = Some(MoveOption::Draw),
+        _ => (),
+    };
 
     (command.replace("DRAW", "").to_string(), option)
 }
@@ -32,17 +48,9 @@ pub(crate) fn ask_for_side() -> Color {
     // keep asking if the input is wrong
     loop {
         println!("Choose which side you want to play: W for white, B for black.");
-        print!(">>> ");
-        let _ = stdout().flush();
-
-        let mut line_parsed = String::new();
-        stdin()
-            .read_line(&mut line_parsed)
-            .expect("IO Eroor: failed to read line");
-
-        let command = line_parsed.trim();
+        let command = read_input();
 
-        match command {
+        match command.as_str() {
             "W" => returned_color = Color::White,
             "B" => returned_color = Color::Black,
             _ => {
@@ -59,7 +67,8 @@ pub(crate) fn get_game_mode() -> u8 {
     println!(
         "minac - minac Is Not A Chessboard
 -----------------------
-All moves must be in SAN (Standard Algebraic Notation).
+Moves are in SAN (Standard Algebraic Notation) or UCI, or just the destination
+when only one piece can go there.
 
 Choose either:
 * [0] offline, 2 players
@@ -67,13 +76,218 @@ Choose either:
 * [2] online
 "
     );
-    print!(">>> ");
-    let _ = stdout().flush();
 
-    let mut choice = String::new();
-    stdin()
-        .read_line(&mut choice)
-        .expect("IO Eroor: failed to read line.");
+    read_input().parse().expect("Please type a number.")
+}
+
+pub(crate) fn get_online_mode() -> u8 {
+    println!(
+        "Choose either:
+* [0] play the Lichess AI
+* [1] challenge a Lichess user
+* [2] accept a challenge you received
+* [3] seek an opponent in the lobby
+"
+    );
+
+    // keep asking if the input is wrong
+    loop {
+        match read_input().parse() {
+            Ok(mode) if mode <= 3 => return mode,
+            _ => println!("Wrong input, try again."),
+        };
+    }
+}
+
+/// Ask for the level of the Lichess AI, from 1 to 8.
+pub(crate) fn ask_for_ai_level(default: u32) -> u32 {
+    // keep asking if the input is wrong
+    loop {
+        println!("Choose the level of the AI, from 1 to 8. Press enter to keep {default}.");
+
+        let command = read_input();
+        if command.is_empty() {
+            return default;
+        }
+        match command.parse() {
+            Ok(level) if (1..=8).contains(&level) => return level,
+            _ => println!("Wrong input, try again."),
+        };
+    }
+}
+
+/// Ask for the color to play in a Lichess challenge, random by default.
+pub(crate) fn ask_for_challenge_color() -> lichess_api::model::Color {
+    // keep asking if the input is wrong
+    loop {
+        println!("Choose your color: W for white, B for black, R or enter for random.");
+
+        match read_input().as_str() {
+            "W" => return lichess_api::model::Color::White,
+            "B" => return lichess_api::model::Color::Black,
+            "R" | "" => return lichess_api::model::Color::Random,
+            _ => println!("Wrong input, try again."),
+        };
+    }
+}
+
+/// Ask why a challenge is declined, the reasons Lichess shows to the challenger.
+pub(crate) fn ask_for_decline_reason() -> DeclineReason {
+    let reasons = [
+        ("generic", DeclineReason::Generic),
+        ("not now", DeclineReason::Later),
+        ("too fast", DeclineReason::TooFast),
+        ("too slow", DeclineReason::TooSlow),
+        ("wrong time control", DeclineReason::TimeControl),
+        ("rated games only", DeclineReason::Rated),
+        ("casual games only", DeclineReason::Casual),
+        ("standard chess only", DeclineReason::Standard),
+        ("not this variant", DeclineReason::Variant),
+        ("no bots", DeclineReason::NoBot),
+        ("bots only", DeclineReason::OnlyBot),
+    ];
+
+    println!("Choose the reason, or press enter for generic:");
+    for (i, (reason, _)) in reasons.iter().enumerate() {
+        println!("* [{i}] {reason}");
+    }
+
+    // keep asking if the input is wrong
+    loop {
+        let command = read_input();
+        if command.is_empty() {
+            return DeclineReason::Generic;
+        }
+        match command.parse::<usize>() {
+            Ok(index) if index < reasons.len() => return reasons[index].1.clone(),
+            _ => println!("Wrong input, try again."),
+        };
+    }
+}
+
+/// Ask for a line of text, e.g. a Lichess username. Keeps asking while it is empty.
+pub(crate) fn ask_for_text(question: &str) -> String {
+    loop {
+        println!("{question}");
+
+        let command = read_input();
+        if !command.is_empty() {
+            return command;
+        }
+    }
+}
+
+/// Ask for a number from 0 to `count - 1`, e.g. to pick an item of a list.
+pub(crate) fn ask_for_index(count: usize) -> usize {
+    // keep asking if the input is wrong
+    loop {
+        match read_input().parse() {
+            Ok(index) if index < count => return index,
+            _ => println!("Wrong input, try again."),
+        };
+    }
+}
+
+/// Ask for the time control of the game, `None` meaning no clock.
+pub(crate) fn ask_for_time_control(default: TimeControl) -> Option<TimeControl> {
+    // keep asking if the input is wrong
+    loop {
+        println!(
+            "Choose the time control, in minutes and seconds. Examples: 5+3, 15d5 (delay), 40/90+30:30+30."
+        );
+        println!("Enter - for no clock, or press enter to keep {default}.");
+
+        let command = read_input();
+        match command.as_str() {
+            "" => return Some(default),
+            "-" => return None,
+            _ => match command.parse() {
+                Ok(time_control) => return Some(time_control),
+                Err(e) => println!("Wrong time control ({e}), try again."),
+            },
+        };
+    }
+}
+
+pub(crate) fn ask_for_engine_strength(
+    default: EngineStrength,
+    elo_range: Option<(i64, i64)>,
+) -> EngineStrength {
+    // keep asking if the input is wrong
+    loop {
+        match elo_range {
+            Some((min, max)) => println!(
+                "Choose the engine strength: E followed by a rating from {min} to {max} (example: E1500),"
+            ),
+            None => println!("Choose the engine strength:"),
+        };
+        println!(
+            "S followed by a skill level from 0 to 20 (example: S5). Press enter to keep {:?}.",
+            default
+        );
+
+        let command = read_input();
+        if command.is_empty() {
+            return default;
+        }
+
+        let (kind, value) = command.split_at_checked(1).unwrap_or_default();
+        match (kind, value.parse::<i64>(), elo_range) {
+            // kept within the range by the engine session
+            ("E", Ok(elo), Some(_)) => return EngineStrength::Elo(elo),
+            ("S", Ok(level), _) if (0..=20).contains(&level) => {
+                return EngineStrength::SkillLevel(level);
+            }
+            _ => println!("Wrong input, try again."),
+        };
+    }
+}
+
+pub(crate) fn ask_for_search_limit(default: SearchLimit) -> SearchLimit {
+    // keep asking if the input is wrong
+    loop {
+        println!(
+            "Choose how long the engine thinks per move: D followed by a depth, N by a number of nodes,"
+        );
+        println!(
+            "or T by a time in milliseconds (example: T2000). Press enter to keep {:?}.",
+            default
+        );
+
+        let command = read_input();
+        if command.is_empty() {
+            return default;
+        }
+
+        let (kind, value) = command.split_at_checked(1).unwrap_or_default();
+        match kind {
+            "D" => match value.parse() {
+                Ok(depth) => return SearchLimit::Depth(depth),
+                Err(_) => println!("Wrong depth, try again."),
+            },
+            "N" => match value.parse() {
+                Ok(nodes) => return SearchLimit::Nodes(nodes),
+                Err(_) => println!("Wrong number of nodes, try again."),
+            },
+            "T" => match value.parse() {
+                Ok(ms) => return SearchLimit::MoveTime(ms),
+                Err(_) => println!("Wrong time, try again."),
+            },
+            _ => println!("Wrong input, try again."),
+        };
+    }
+}
+
+pub(crate) fn ask_yes_no(question: &str) -> bool {
+    println!("{question} [y/N]");
+    matches!(read_input().as_str(), "y" | "Y")
+}
+
+fn read_input() -> String {
+    read_input_within(None).expect("Should never happen: no timeout given")
+}
 
-    choice.trim().parse().expect("Please type a number.")
+/// Read a trimmed line from stdin, or `None` if nothing was entered before the timeout.
+fn read_input_within(timeout: Option<Duration>) -> Option<String> {
+    read_line_blocking(timeout)
 }